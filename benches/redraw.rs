@@ -0,0 +1,109 @@
+// Per-keystroke rendering cost on a 4K output: `cargo bench --bench redraw`
+use std::fs::File;
+use std::mem::ManuallyDrop;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::FromRawFd;
+use std::time::{Duration, Instant};
+
+use keynav_wayland::render::{Damage, RenderManager};
+
+const WIDTH: u32 = 3840;
+const HEIGHT: u32 = 2160;
+const ROUNDS: u32 = 20;
+
+// A typical session: halve the region a few times, nudge it around and move it
+// across the diagonal
+fn keystrokes() -> Vec<cairo::Rectangle> {
+    let mut regions = Vec::new();
+    let mut region = cairo::Rectangle {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+    for i in 0..8 {
+        if i % 2 == 0 {
+            region.width /= 2.0;
+        } else {
+            region.height /= 2.0;
+        }
+        regions.push(region);
+    }
+    for _ in 0..4 {
+        region.x += region.width;
+        regions.push(region);
+    }
+    for _ in 0..4 {
+        region.x -= region.width;
+        region.y += region.height;
+        regions.push(region);
+    }
+    regions
+}
+
+// What the compositor does with a commit: copy the damaged rows of the buffer
+// into its own copy of the surface
+fn upload(renderer: &RenderManager, damage: &[Damage], texture: &mut [u8]) {
+    // Borrowed from the renderer, which closes it
+    let buffer = ManuallyDrop::new(unsafe { File::from_raw_fd(renderer.get_shm_fd()) });
+    let stride = renderer.get_stride() as usize;
+    for (x, y, width, height) in damage {
+        for row in *y as usize..(y + height) as usize {
+            let start = row * stride + *x as usize * 4;
+            let end = start + *width as usize * 4;
+            buffer
+                .read_exact_at(&mut texture[start..end], start as u64)
+                .unwrap();
+        }
+    }
+}
+
+fn bench(name: &str, full: bool) {
+    let mut renderer = RenderManager::init(cairo::Format::ARgb32, WIDTH, HEIGHT).unwrap();
+    let regions = keystrokes();
+    let mut texture = vec![0; renderer.get_buf_size() as usize];
+    let mut total = Duration::ZERO;
+    let mut commit = Duration::ZERO;
+    let mut pixels = 0i64;
+    for _ in 0..ROUNDS {
+        for region in &regions {
+            renderer.update_active_region(*region);
+            if full {
+                renderer.invalidate();
+            }
+            let start = Instant::now();
+            renderer.redraw().unwrap();
+            total += start.elapsed();
+            let damage = renderer.take_damage();
+            let start = Instant::now();
+            upload(&renderer, &damage, &mut texture);
+            commit += start.elapsed();
+            pixels += damage
+                .iter()
+                .map(|(_, _, width, height)| *width as i64 * *height as i64)
+                .sum::<i64>();
+        }
+        renderer.update_active_region(cairo::Rectangle {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        });
+        renderer.redraw().unwrap();
+        renderer.take_damage();
+    }
+    let keystrokes = ROUNDS as i64 * regions.len() as i64;
+    println!(
+        "{:>8}: {:>10.3?} redraw and {:>10.3?} commit per keystroke, {:>9} pixels damaged per keystroke",
+        name,
+        total / keystrokes as u32,
+        commit / keystrokes as u32,
+        pixels / keystrokes
+    );
+}
+
+fn main() {
+    println!("{}x{}", WIDTH, HEIGHT);
+    bench("full", true);
+    bench("partial", false);
+}
@@ -0,0 +1,23 @@
+use std::env;
+use std::path::PathBuf;
+
+use wayland_scanner::{generate_code, Side};
+
+// Protocols that wayland-protocols doesn't ship, vendored in protocols/
+const PROTOCOLS: [(&str, &str); 2] = [
+    ("fake-input", "fake_input"),
+    ("virtual-keyboard-unstable-v1", "virtual_keyboard"),
+];
+
+fn main() {
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    for (file, module) in PROTOCOLS {
+        let path = format!("protocols/{}.xml", file);
+        println!("cargo:rerun-if-changed={}", path);
+        generate_code(
+            &path,
+            out_dir.join(format!("{}_client_api.rs", module)),
+            Side::Client,
+        );
+    }
+}
@@ -1,39 +1,213 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use either::*;
 use log::*;
 
 use wayland_client::{
     protocol::{
-        wl_buffer, wl_compositor, wl_keyboard, wl_pointer, wl_seat, wl_shm, wl_shm_pool, wl_surface,
+        wl_buffer, wl_callback, wl_compositor, wl_data_device_manager, wl_keyboard, wl_output,
+        wl_pointer, wl_seat, wl_shm, wl_shm_pool, wl_surface,
     },
-    Display, EventQueue, GlobalManager, Main,
+    Display, EventQueue, GlobalEvent, GlobalManager, Main,
 };
 use wayland_protocols::wlr::unstable::{
+    data_control::v1::client::zwlr_data_control_manager_v1,
+    foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1,
     layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1},
-    virtual_pointer::v1::client::{zwlr_virtual_pointer_manager_v1, zwlr_virtual_pointer_v1},
+    screencopy::v1::client::zwlr_screencopy_manager_v1,
 };
+use wayland_protocols::xdg_shell::client::{xdg_surface, xdg_toplevel, xdg_wm_base};
 
 use xkbcommon::xkb;
 
-use crate::config::{Config, KeynavAction, MouseButton, RawConfig};
+use crate::clipboard::Clipboard;
+use crate::config::{Config, InitialRegion, KeynavAction, RawConfig, SurfaceChoice};
+use crate::keyboard::{Input, VirtualKeyboard};
+use crate::navigator::{Command, Navigator, DEFAULT_PIXELS_PER_MM};
+use crate::pointer::{self, PointerBackend};
+use crate::protocols::virtual_keyboard::client::zwp_virtual_keyboard_manager_v1;
 use crate::render::RenderManager;
+use crate::screencopy::{capture_output, Capture};
+use crate::screenshot;
+use crate::state::{self, Regions};
+use crate::sway;
+use crate::windows::{self, Picker, Toplevels, Typed};
+
+// Of the xdg-shell fallback
+const APP_ID: &str = "keynav-wayland";
+// What the last region is called in [App.last_regions]
+const LAST_REGION: &str = "last";
+// Counts stop growing here, so that a slip of the finger can't keep us busy
+const MAX_COUNT: u32 = 999;
+// Sleeps and holds stop here, as we don't react to keys while they last
+const MAX_SLEEP: Duration = Duration::from_secs(10);
+// How long we wait for the compositor to take keyboard focus away from the
+// hidden overlay before typing anyway
+const FOCUS_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+struct OutputInfo {
+    output: wl_output::WlOutput,
+    // The name of the wl_output global, so we can forget about it when it is
+    // removed
+    global_name: u32,
+    // In millimeters and already rotated to match the logical orientation of
+    // the output
+    physical_size: (i32, i32),
+    // Of the top left corner in the compositor's global space
+    position: (i32, i32),
+    // Buffer pixels per surface pixel that look sharp on it, only sent since
+    // version 2 of wl_output
+    scale: i32,
+    // Like DP-1, only sent since version 4 of wl_output
+    name: String,
+    // Like "Dell Inc. DELL U2720Q", also since version 4
+    description: String,
+}
+
+// The role of our surface, ie. how it gets shown
+enum Role {
+    Layer(Main<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>),
+    // For compositors without layer shell, covering the output like a
+    // fullscreen window would
+    Toplevel {
+        xdg_surface: Main<xdg_surface::XdgSurface>,
+        toplevel: Main<xdg_toplevel::XdgToplevel>,
+    },
+}
+
+// Whether [globals] (as from [GlobalManager::list]) has [interface] in at least
+// [version]
+fn has_global(globals: &[(u32, String, u32)], interface: &str, version: u32) -> bool {
+    globals
+        .iter()
+        .any(|(_, name, available)| name == interface && *available >= version)
+}
+
+// Whether pressing [keysym] changes what the next key means rather than
+// being a key of its own
+fn is_modifier(keysym: xkb::Keysym) -> bool {
+    (xkb::KEY_Shift_L..=xkb::KEY_Hyper_R).contains(&keysym)
+        || (xkb::KEY_ISO_Lock..=xkb::KEY_ISO_Level5_Lock).contains(&keysym)
+        || keysym == xkb::KEY_Mode_switch
+        || keysym == xkb::KEY_Num_Lock
+}
+
+// CapsLock and NumLock, which stay on while typing rather than being held for
+// a binding
+fn lock_modifiers(keymap: &xkb::Keymap) -> u32 {
+    [xkb::MOD_NAME_CAPS, xkb::MOD_NAME_NUM]
+        .iter()
+        .map(|name| keymap.mod_get_index(name))
+        .filter(|index| *index < u32::BITS)
+        .fold(0, |mask, index| mask | 1 << index)
+}
+
+// Lists everything we need that the compositor lacks at once, rather than
+// failing on whichever we happen to bind first
+fn check_protocols(globals: &[(u32, String, u32)], surface: SurfaceChoice) -> Result<(), String> {
+    let describe = |interface: &str, version: u32| match version {
+        1 => interface.to_string(),
+        _ => format!("{} (version {})", interface, version),
+    };
+    let mut missing = Vec::new();
+    for (interface, version) in [("wl_compositor", 4), ("wl_shm", 1), ("wl_seat", 1)] {
+        if !has_global(globals, interface, version) {
+            missing.push(describe(interface, version));
+        }
+    }
+    let layer_shell = has_global(globals, "zwlr_layer_shell_v1", 4);
+    let xdg_shell = has_global(globals, "xdg_wm_base", 1);
+    match surface {
+        SurfaceChoice::Auto if !layer_shell && !xdg_shell => missing.push(format!(
+            "{} or {}",
+            describe("zwlr_layer_shell_v1", 4),
+            describe("xdg_wm_base", 1)
+        )),
+        SurfaceChoice::LayerShell if !layer_shell => {
+            missing.push(describe("zwlr_layer_shell_v1", 4))
+        }
+        SurfaceChoice::XdgShell if !xdg_shell => missing.push(describe("xdg_wm_base", 1)),
+        _ => (),
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Compositor does not support these required protocols: {}",
+            missing.join(", ")
+        ))
+    }
+}
 
 // Need to separate [App.DataData] and [App.Data] so that we can borrow the event queue
 // mutably to dispatch events without simultaneously borrowing the rest of the
 // app.data
 struct App {
     config: Either<Config, RawConfig>,
-    pointer_pos: (i32, i32),
+    // To flush requests before a sleep
+    display: Display,
+    // To run [App.pending] with once a sleep is over
+    pointer: Option<Rc<dyn PointerBackend>>,
+    navigator: Navigator,
     keyboard_state: Option<xkb::State>,
-    button_state: HashMap<u32, wl_pointer::ButtonState>,
     should_end: bool,
     renderer: Rc<RefCell<RenderManager>>,
     surface: Main<wl_surface::WlSurface>,
     pool: Main<wl_shm_pool::WlShmPool>,
     buffer: Main<wl_buffer::WlBuffer>,
+    // Whether we are waiting for the compositor to show our last commit
+    frame_pending: Rc<Cell<bool>>,
+    // Timestamp of a frame callback that arrived since the last commit
+    frame_time: Rc<Cell<Option<u32>>>,
+    // Keyed by the protocol id of the wl_output
+    outputs: Rc<RefCell<HashMap<u32, OutputInfo>>>,
+    current_output: Option<u32>,
+    // What the outputs looked like before we covered them, keyed like
+    // [App.outputs]. Only taken if an action needs it.
+    captures: HashMap<u32, Capture>,
+    clipboard: Option<Clipboard>,
+    // Of the latest input event, which the clipboard needs
+    serial: u32,
+    // Whether we took the overlay down, but are still around to serve the
+    // clipboard
+    hidden: bool,
+    toplevels: Option<Toplevels>,
+    seat: Option<wl_seat::WlSeat>,
+    // While picking a window: the [windows::Window::id] of each, and where it
+    // is on our output if we could find out
+    picker: Option<Picker<(u64, Option<cairo::Rectangle>)>>,
+    // Whether any bindings ran yet, after which the initial region of another
+    // output no longer applies
+    acted: bool,
+    marks: Regions,
+    // Where [App.marks] are saved, None if we don't know where to
+    marks_path: Option<PathBuf>,
+    // Where the region was when we last ended, for initial-region last
+    last_regions: Regions,
+    last_regions_path: Option<PathBuf>,
+    // Typed with the count keys, for the next binding
+    count: Option<u32>,
+    // The actions of the last binding that ran and how often, for `repeat`
+    last_binding: (Vec<KeynavAction>, u32),
+    virtual_keyboard: Option<VirtualKeyboard>,
+    // What the key and type actions of a binding queued, to send once the
+    // overlay no longer has keyboard focus
+    typing: Vec<Input>,
+    // Whether our wl_keyboard has focus, in which case typed keys would come
+    // back to us rather than go to the window under the overlay
+    keyboard_focused: bool,
+    // The commands of a binding left to run after a sleep, and when the sleep
+    // is over
+    pending: VecDeque<Command>,
+    resume_at: Option<Instant>,
+    // Where print and pick-color write their lines
+    stdout: Box<dyn Write>,
 }
 
 impl App {
@@ -43,7 +217,85 @@ impl App {
     ) -> Result<Rc<RefCell<Self>>, String> {
         let attached_display = (event_queue.display()).clone().attach(event_queue.token());
 
-        let globals = GlobalManager::new(&attached_display);
+        let outputs: Rc<RefCell<HashMap<u32, OutputInfo>>> = Rc::new(RefCell::new(HashMap::new()));
+        let globals = {
+            let outputs = outputs.clone();
+            GlobalManager::new_with_cb(&attached_display, move |event, registry, _| match event {
+                GlobalEvent::New {
+                    id,
+                    interface,
+                    version,
+                } if interface == "wl_output" => {
+                    let output = registry.bind::<wl_output::WlOutput>(u32::min(version, 4), id);
+                    outputs.borrow_mut().insert(
+                        output.as_ref().id(),
+                        OutputInfo {
+                            output: output.detach(),
+                            global_name: id,
+                            physical_size: (0, 0),
+                            position: (0, 0),
+                            scale: 1,
+                            name: String::new(),
+                            description: String::new(),
+                        },
+                    );
+                    let outputs = outputs.clone();
+                    output.quick_assign(move |output, event, _| {
+                        let mut outputs = outputs.borrow_mut();
+                        let info = match outputs.get_mut(&output.as_ref().id()) {
+                            Some(info) => info,
+                            None => return,
+                        };
+                        match event {
+                            wl_output::Event::Geometry {
+                                x,
+                                y,
+                                physical_width,
+                                physical_height,
+                                transform,
+                                ..
+                            } => {
+                                trace!(
+                                    "Output is {}x{}mm at {}, {}",
+                                    physical_width,
+                                    physical_height,
+                                    x,
+                                    y
+                                );
+                                info.position = (x, y);
+                                info.physical_size = match transform {
+                                    wl_output::Transform::_90
+                                    | wl_output::Transform::_270
+                                    | wl_output::Transform::Flipped90
+                                    | wl_output::Transform::Flipped270 => {
+                                        (physical_height, physical_width)
+                                    }
+                                    _ => (physical_width, physical_height),
+                                };
+                            }
+                            wl_output::Event::Scale { factor } => {
+                                trace!("Output has scale {}", factor);
+                                info.scale = factor;
+                            }
+                            wl_output::Event::Name { name } => {
+                                trace!("Output is called {}", name);
+                                info.name = name;
+                            }
+                            wl_output::Event::Description { description } => {
+                                info.description = description;
+                            }
+                            _ => (),
+                        }
+                    });
+                }
+                GlobalEvent::Removed { id, interface } if interface == "wl_output" => {
+                    outputs
+                        .borrow_mut()
+                        .retain(|_, info| info.global_name != id);
+                }
+                _ => (),
+            })
+        };
 
         // Make a synchronized roundtrip to the wayland server.
         //
@@ -54,31 +306,105 @@ impl App {
             .sync_roundtrip(&mut (), |_, _, _| unreachable!())
             .unwrap();
 
+        check_protocols(&globals.list(), config.settings.surface)?;
+
+        let shm = globals.instantiate_exact::<wl_shm::WlShm>(1).unwrap();
+
+        // This has to happen before our surface is mapped, so that we don't
+        // capture ourselves, and before it is created, so that the dispatching
+        // doesn't swallow its first configure
+        let mut captures = HashMap::new();
+        // Profiles included, as we don't know which window is focused yet
+        if config
+            .mappings
+            .iter()
+            .chain(config.profiles.iter().flat_map(|profile| &profile.mappings))
+            .flat_map(|(_, actions)| actions)
+            .any(KeynavAction::needs_screen_contents)
+        {
+            match globals
+                .instantiate_range::<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1>(1, 2)
+            {
+                Ok(manager) => {
+                    let outputs: Vec<_> = outputs
+                        .borrow()
+                        .iter()
+                        .map(|(id, info)| (*id, info.output.clone()))
+                        .collect();
+                    for (id, output) in outputs {
+                        match capture_output(&manager, &shm, &output, event_queue) {
+                            Ok(capture) => {
+                                captures.insert(id, capture);
+                            }
+                            Err(err) => warn!("Failed to capture output {}: {}", id, err),
+                        }
+                    }
+                    manager.destroy();
+                }
+                Err(_) => warn!(
+                    "Compositor does not support zwlr_screencopy_manager_v1, actions that need \
+                     to see the screen won't work"
+                ),
+            }
+        }
         let compositor = globals
             .instantiate_exact::<wl_compositor::WlCompositor>(4)
             .unwrap();
         let surface = compositor.create_surface();
 
-        let layer_shell = globals
-            .instantiate_exact::<zwlr_layer_shell_v1::ZwlrLayerShellV1>(4)
-            .expect("Compositor does not support zwlr_layer_shell_v1");
-        let layer_surface = layer_shell.get_layer_surface(
-            &surface,
-            None,
-            zwlr_layer_shell_v1::Layer::Overlay,
-            "keynav".to_string(),
-        );
-        layer_surface.set_size(0, 0);
-        layer_surface.set_anchor(
-            zwlr_layer_surface_v1::Anchor::Top
-                | zwlr_layer_surface_v1::Anchor::Bottom
-                | zwlr_layer_surface_v1::Anchor::Left
-                | zwlr_layer_surface_v1::Anchor::Right,
-        );
+        let use_layer_shell = match config.settings.surface {
+            SurfaceChoice::LayerShell => true,
+            SurfaceChoice::XdgShell => false,
+            SurfaceChoice::Auto => has_global(&globals.list(), "zwlr_layer_shell_v1", 4),
+        };
+        let role = if use_layer_shell {
+            let layer_shell = globals
+                .instantiate_exact::<zwlr_layer_shell_v1::ZwlrLayerShellV1>(4)
+                .unwrap();
+            let layer_surface = layer_shell.get_layer_surface(
+                &surface,
+                None,
+                zwlr_layer_shell_v1::Layer::Overlay,
+                "keynav".to_string(),
+            );
+            layer_surface.set_size(0, 0);
+            layer_surface.set_anchor(
+                zwlr_layer_surface_v1::Anchor::Top
+                    | zwlr_layer_surface_v1::Anchor::Bottom
+                    | zwlr_layer_surface_v1::Anchor::Left
+                    | zwlr_layer_surface_v1::Anchor::Right,
+            );
 
-        layer_surface.set_exclusive_zone(-1);
-        layer_surface
-            .set_keyboard_interactivity(zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive);
+            layer_surface.set_exclusive_zone(-1);
+            layer_surface.set_keyboard_interactivity(
+                zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive,
+            );
+            Role::Layer(layer_surface)
+        } else {
+            debug!("Using a fullscreen xdg_toplevel instead of a layer surface");
+            let wm_base = globals
+                .instantiate_exact::<xdg_wm_base::XdgWmBase>(1)
+                .unwrap();
+            wm_base.quick_assign(|wm_base, event, _| {
+                if let xdg_wm_base::Event::Ping { serial } = event {
+                    wm_base.pong(serial);
+                }
+            });
+            let xdg_surface = wm_base.get_xdg_surface(&surface);
+            let toplevel = xdg_surface.get_toplevel();
+            toplevel.set_title("keynav".to_string());
+            toplevel.set_app_id(APP_ID.to_string());
+            toplevel.set_fullscreen(None);
+            // Clicks go to the windows below right from the first commit, as
+            // nothing stops a fullscreen window from being clicked before the
+            // pointer enters it. This also means we never learn where the
+            // pointer is.
+            surface.set_input_region(Some(&compositor.create_region()));
+            Role::Toplevel {
+                xdg_surface,
+                toplevel,
+            }
+        };
 
         trace!("Initial commit of surface (trigger configure)");
         surface.commit();
@@ -87,8 +413,11 @@ impl App {
         let renderer: Rc<RefCell<_>> = Rc::new(RefCell::new(
             RenderManager::init(cairo::Format::ARgb32, 100, 100).unwrap(),
         ));
+        renderer.borrow_mut().set_animation(
+            config.settings.animation_duration,
+            config.settings.animation_easing,
+        );
 
-        let shm = globals.instantiate_exact::<wl_shm::WlShm>(1).unwrap();
         let pool = shm.create_pool(
             renderer.borrow().get_shm_fd(),
             (renderer.borrow().get_buf_size()) as i32,
@@ -101,35 +430,139 @@ impl App {
             wl_shm::Format::Argb8888,
         );
 
+        let pointer_backend_choice = config.settings.pointer_backend;
+        let marks_path = state::state_path("marks");
+        let last_regions_path = state::state_path("last-region");
+        if marks_path.is_none() {
+            warn!(
+                "Neither $XDG_STATE_HOME nor $HOME set, marks and the last region won't be saved"
+            );
+        }
+        let load = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map(|path| {
+                    Regions::load(path).unwrap_or_else(|err| {
+                        warn!("{}", err);
+                        Regions::default()
+                    })
+                })
+                .unwrap_or_default()
+        };
+        let (marks, last_regions) = (load(&marks_path), load(&last_regions_path));
         let app = Rc::new(RefCell::new(App {
+            navigator: Navigator::new(config.settings.clone()),
             config: Right(config),
+            display: event_queue.display().clone(),
+            pointer: None,
             keyboard_state: None,
-            button_state: HashMap::new(),
-            pointer_pos: (0, 0),
             should_end: false,
             renderer,
             surface,
             pool,
             buffer,
+            frame_pending: Rc::new(Cell::new(false)),
+            frame_time: Rc::new(Cell::new(None)),
+            outputs,
+            current_output: None,
+            captures,
+            clipboard: None,
+            serial: 0,
+            hidden: false,
+            toplevels: None,
+            seat: None,
+            picker: None,
+            acted: false,
+            marks,
+            marks_path,
+            last_regions,
+            last_regions_path,
+            count: None,
+            last_binding: (Vec::new(), 1),
+            virtual_keyboard: None,
+            typing: Vec::new(),
+            keyboard_focused: false,
+            pending: VecDeque::new(),
+            resume_at: None,
+            stdout: Box::new(std::io::stdout()),
         }));
 
+        {
+            let surface = app.borrow().surface.clone();
+            let app = app.clone();
+            surface.quick_assign(move |_, event, _| match event {
+                wl_surface::Event::Enter { output } => {
+                    trace!("Surface entered output {}", output.as_ref().id());
+                    let mut app = app.borrow_mut();
+                    app.current_output = Some(output.as_ref().id());
+                    app.apply_output_settings();
+                    app.apply_output_scale();
+                }
+                wl_surface::Event::Leave { output } => {
+                    let mut app = app.borrow_mut();
+                    if app.current_output == Some(output.as_ref().id()) {
+                        app.current_output = None;
+                    }
+                }
+                _ => (),
+            });
+        }
+
         {
             // Need to start listening to keyboard events as soon as we create the layer_surface otherwise we don't gain focus immediately
             let app = app.clone();
             let renderer = app.borrow().renderer.clone();
-            layer_surface.quick_assign(move |layer_surface, event, _| match event {
-                zwlr_layer_surface_v1::Event::Configure {
-                    width,
-                    height,
-                    serial,
+            match role {
+                Role::Layer(layer_surface) => {
+                    layer_surface.quick_assign(move |layer_surface, event, _| {
+                        if let zwlr_layer_surface_v1::Event::Configure {
+                            width,
+                            height,
+                            serial,
+                        } = event
+                        {
+                            trace!("Configure: {}x{}", width, height);
+                            renderer.borrow_mut().set_bounds(width, height).unwrap();
+                            layer_surface.ack_configure(serial);
+                            app.borrow_mut().rebind();
+                        }
+                    });
+                }
+                Role::Toplevel {
+                    xdg_surface,
+                    toplevel,
                 } => {
-                    trace!("Configure: {}x{}", width, height);
-                    renderer.borrow_mut().set_bounds(width, height).unwrap();
-                    layer_surface.ack_configure(serial);
-                    app.borrow_mut().rebind();
+                    // The size comes with the toplevel's configure, but only
+                    // applies once the surface's configure follows
+                    let size = Rc::new(Cell::new((0, 0)));
+                    {
+                        let size = size.clone();
+                        let app = app.clone();
+                        toplevel.quick_assign(move |_, event, _| match event {
+                            xdg_toplevel::Event::Configure { width, height, .. } => {
+                                size.set((width, height))
+                            }
+                            xdg_toplevel::Event::Close => app.borrow_mut().end(),
+                            _ => (),
+                        });
+                    }
+                    xdg_surface.quick_assign(move |xdg_surface, event, _| {
+                        if let xdg_surface::Event::Configure { serial } = event {
+                            let (width, height) = size.get();
+                            trace!("Configure: {}x{}", width, height);
+                            xdg_surface.ack_configure(serial);
+                            // Zero would leave the size up to us, which a
+                            // fullscreen surface shouldn't get
+                            if width > 0 && height > 0 {
+                                renderer
+                                    .borrow_mut()
+                                    .set_bounds(width as u32, height as u32)
+                                    .unwrap();
+                                app.borrow_mut().rebind();
+                            }
+                        }
+                    });
                 }
-                _ => (),
-            });
+            }
         }
 
         event_queue
@@ -137,60 +570,95 @@ impl App {
             .unwrap();
 
         {
-            let virtual_pointer_manager = globals
-                .instantiate_exact::<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1>(
-                    2,
-                )
-                .expect("Compositor should support virtual pointer");
             let app = app.clone();
             let mut keyboard_created = false;
             let mut pointer_created = false;
-            globals
-                .instantiate_exact::<wl_seat::WlSeat>(1)
-                .unwrap()
-                .quick_assign(move |seat, event, _| {
-                    // The capabilities of a seat are known at runtime and we retrieve
-                    // them via an events. 3 capabilities exists: pointer, keyboard, and touch
-                    // we are only interested in pointer & keyboard here
-                    use wayland_client::protocol::wl_seat::{Capability, Event as SeatEvent};
-
-                    let virtual_pointer =
-                        virtual_pointer_manager.create_virtual_pointer(Some(&seat));
-                    let region = compositor.create_region();
-                    let app = app.clone();
-                    if let SeatEvent::Capabilities { capabilities } = event {
-                        if !pointer_created && capabilities.contains(Capability::Pointer) {
-                            let app = app.clone();
-                            pointer_created = true;
-                            seat.get_pointer().quick_assign(
-                                move |_pointer, event, _| match event {
-                                    wl_pointer::Event::Enter {
-                                        surface_x,
-                                        surface_y,
-                                        ..
-                                    } => {
-                                        trace!("Pointer entered at {}, {}", surface_x, surface_y);
-                                        let mut app = app.borrow_mut();
-                                        app.surface.set_input_region(Some(&region));
-                                        app.pointer_pos = (surface_x as i32, surface_y as i32);
-                                    }
-                                    _ => {}
-                                },
-                            );
-                        };
-                    }
-                    if let SeatEvent::Capabilities { capabilities } = event {
-                        if !keyboard_created && capabilities.contains(Capability::Keyboard) {
-                            // create the keyboard only once
-                            keyboard_created = true;
-                            seat.get_keyboard()
-                                .quick_assign(move |_keyboard, event, _| {
-                                    app.borrow_mut()
-                                        .handle_keyboard_event(&virtual_pointer, event);
-                                });
-                        };
-                    }
-                });
+            let seat = globals.instantiate_exact::<wl_seat::WlSeat>(1).unwrap();
+            let pointer_backend: Rc<dyn PointerBackend> =
+                pointer::select(pointer_backend_choice, &globals, &seat)?.into();
+            app.borrow_mut().pointer = Some(pointer_backend.clone());
+            // Data control works without focus, so prefer it where we have it
+            app.borrow_mut().clipboard =
+                match globals
+                    .instantiate_range::<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1>(
+                        1, 2,
+                    ) {
+                    Ok(manager) => Some(Clipboard::with_data_control(manager, &seat)),
+                    Err(_) => match globals
+                        .instantiate_range::<wl_data_device_manager::WlDataDeviceManager>(1, 3)
+                    {
+                        Ok(manager) => Some(Clipboard::with_data_device(manager, &seat)),
+                        Err(_) => {
+                            warn!("Compositor does not support wl_data_device_manager, copy won't work");
+                            None
+                        }
+                    },
+                };
+            app.borrow_mut().toplevels = match globals
+                .instantiate_range::<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1>(
+                    1, 3,
+                ) {
+                Ok(manager) => Some(Toplevels::new(manager)),
+                Err(_) => {
+                    debug!("Compositor does not support zwlr_foreign_toplevel_manager_v1");
+                    None
+                }
+            };
+            app.borrow_mut().virtual_keyboard = match globals
+                .instantiate_exact::<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1>(1)
+            {
+                Ok(manager) => Some(VirtualKeyboard::new(manager)),
+                Err(_) => {
+                    debug!("Compositor does not support zwp_virtual_keyboard_manager_v1");
+                    None
+                }
+            };
+            app.borrow_mut().seat = Some(seat.detach());
+            seat.quick_assign(move |seat, event, _| {
+                // The capabilities of a seat are known at runtime and we retrieve
+                // them via an events. 3 capabilities exists: pointer, keyboard, and touch
+                // we are only interested in pointer & keyboard here
+                use wayland_client::protocol::wl_seat::{Capability, Event as SeatEvent};
+
+                let pointer_backend = pointer_backend.clone();
+                let region = compositor.create_region();
+                let app = app.clone();
+                if let SeatEvent::Capabilities { capabilities } = event {
+                    if !pointer_created && capabilities.contains(Capability::Pointer) {
+                        let app = app.clone();
+                        pointer_created = true;
+                        seat.get_pointer().quick_assign(move |_pointer, event, _| {
+                            if let wl_pointer::Event::Enter {
+                                surface_x,
+                                surface_y,
+                                ..
+                            } = event
+                            {
+                                trace!("Pointer entered at {}, {}", surface_x, surface_y);
+                                let mut app = app.borrow_mut();
+                                app.surface.set_input_region(Some(&region));
+                                app.navigator.set_pointer(surface_x, surface_y);
+                                // Only now do we know where to zoom to
+                                if app.navigator.settings().initial_region == InitialRegion::Cursor
+                                {
+                                    app.reset_region();
+                                }
+                            }
+                        });
+                    };
+                }
+                if let SeatEvent::Capabilities { capabilities } = event {
+                    if !keyboard_created && capabilities.contains(Capability::Keyboard) {
+                        // create the keyboard only once
+                        keyboard_created = true;
+                        seat.get_keyboard()
+                            .quick_assign(move |_keyboard, event, _| {
+                                app.borrow_mut()
+                                    .handle_keyboard_event(&*pointer_backend, event);
+                            });
+                    };
+                }
+            });
         }
 
         {
@@ -199,21 +667,26 @@ impl App {
                 .unwrap();
         }
 
-        {
-            let app = app.borrow();
-            app.surface.attach(Some(&app.buffer), 0, 0);
-            app.surface.commit();
-        }
+        app.borrow_mut().apply_output_settings();
+        app.borrow_mut().commit();
 
         {
             event_queue
                 .sync_roundtrip(&mut (), |_, _, _| { /* we ignore unfiltered messages */ })
                 .unwrap();
         }
+        // Compositors say which output the surface is on once it is shown,
+        // which can change the settings it is drawn with
+        app.borrow_mut().commit();
+        if let Err(err) = event_queue.display().flush() {
+            warn!("Failed to flush requests to the compositor: {}", err);
+        }
         Ok(app)
     }
     pub fn rebind(&mut self) {
         trace!("Rebinding");
+        let (width, height) = self.renderer.borrow().get_surface_size();
+        self.navigator.set_surface_size(width, height);
         self.pool
             .resize(self.renderer.borrow().get_buf_size() as i32);
         self.buffer.destroy();
@@ -226,194 +699,209 @@ impl App {
         );
     }
 
-    pub fn commit(&self) {
-        trace!("Commiting");
+    // Draws and commits whatever changed since the last commit. Does nothing
+    // while the previous commit hasn't been shown yet, so changes that come in
+    // faster than the compositor draws end up in a single commit
+    pub fn commit(&mut self) {
+        // Which would show the overlay again
+        if self.hidden {
+            return;
+        }
+        if self.frame_pending.get() {
+            trace!("Waiting for frame callback");
+            return;
+        }
+        let time = self.frame_time.take();
+        let (damage, animating) = {
+            let mut renderer = self.renderer.borrow_mut();
+            if let Some(time) = time {
+                renderer.tick(time);
+            }
+            renderer.redraw().unwrap();
+            (renderer.take_damage(), renderer.is_animating())
+        };
+        if damage.is_empty() && !animating {
+            trace!("Nothing to commit");
+            // Still the time of the latest frame, for an animation that
+            // starts before the next one
+            self.frame_time.set(time);
+            return;
+        }
+        trace!("Commiting {:?}", damage);
 
-        self.surface.attach(Some(&self.buffer), 0, 0);
-        self.surface.damage_buffer(
-            0,
-            0,
-            self.renderer.borrow().get_width() as i32,
-            self.renderer.borrow().get_height() as i32,
-        );
+        // While animating we keep asking for frame callbacks even when nothing
+        // changed (ie. on the first frame), as they are what moves it along
+        if !damage.is_empty() {
+            self.surface.attach(Some(&self.buffer), 0, 0);
+        }
+        for (x, y, width, height) in damage {
+            self.surface.damage_buffer(x, y, width, height);
+        }
+        let frame_pending = self.frame_pending.clone();
+        let frame_time = self.frame_time.clone();
+        self.surface.frame().quick_assign(move |_, event, _| {
+            if let wl_callback::Event::Done { callback_data } = event {
+                frame_pending.set(false);
+                frame_time.set(Some(callback_data));
+            }
+        });
         self.surface.commit();
+        self.frame_pending.set(true);
     }
     pub fn end(&mut self) {
         self.should_end = true;
     }
-    // Returns (x, y, denominator)
-    fn get_center_as_fixed_point(&self) -> (u32, u32, u32) {
-        let rect = self.renderer.borrow().get_active_region();
-        // We scale by extent because motion_absolute takes ints, but we have
-        // normalized scalar coords so we are bsaically just converting the
-        // float to a fixed point with 4 decimal places
-        let extent = 10000;
-        (
-            ((rect.x + rect.width / 2.0) * (extent as f64)) as u32,
-            ((rect.y + rect.height / 2.0) * (extent as f64)) as u32,
-            extent,
-        )
-    }
-    pub fn click(
-        &mut self,
-        virtual_pointer: &zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1,
-        btn: u32,
-    ) {
-        let (x, y, extent) = self.get_center_as_fixed_point();
-        virtual_pointer.motion_absolute(0, x, y, extent, extent);
-        virtual_pointer.frame();
-        virtual_pointer.button(0, btn, wl_pointer::ButtonState::Pressed);
-        virtual_pointer.frame();
-        virtual_pointer.button(0, btn, wl_pointer::ButtonState::Released);
-        virtual_pointer.frame();
-    }
-    pub fn drag(
-        &mut self,
-        virtual_pointer: &zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1,
-        btn: u32,
-    ) {
-        let (x, y, extent) = self.get_center_as_fixed_point();
-        let state = match self.button_state.get(&btn) {
-            Some(wl_pointer::ButtonState::Pressed) => wl_pointer::ButtonState::Released,
-            Some(wl_pointer::ButtonState::Released) | None => wl_pointer::ButtonState::Pressed,
-            _ => panic!("Button state neither pressed nor releasaed"),
-        };
-        self.button_state.insert(btn, state);
-
-        virtual_pointer.motion_absolute(0, x, y, extent, extent);
-        virtual_pointer.frame();
-        virtual_pointer.button(0, btn, state);
-        virtual_pointer.frame();
+    // Whether we have to stay around after ending, to serve the clipboard
+    fn is_serving_clipboard(&self) -> bool {
+        self.clipboard.as_ref().is_some_and(Clipboard::is_serving)
     }
-    pub fn double_click(
-        &mut self,
-        virtual_pointer: &zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1,
-        btn: u32,
-    ) {
-        self.click(virtual_pointer, btn);
-        self.click(virtual_pointer, btn);
-    }
-    pub fn warp(&mut self, virtual_pointer: &zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1) {
-        let (x, y, extent) = self.get_center_as_fixed_point();
-        virtual_pointer.motion_absolute(0, x, y, extent, extent);
-        virtual_pointer.frame();
-    }
-    pub fn cursor_zoom(&mut self, width: u32, height: u32) {
-        let mut renderer = self.renderer.borrow_mut();
-        let (pointer_surface_x, pointer_surface_y) = self.pointer_pos;
-        let pointer_relative_x = (pointer_surface_x as f64) / (renderer.get_width() as f64);
-        let pointer_relative_y = (pointer_surface_y as f64) / (renderer.get_height() as f64);
-
-        let (width, height) = renderer.device_to_user(width as f64, height as f64);
-        renderer.update_active_region(cairo::Rectangle {
-            x: pointer_relative_x - width / 2.0,
-            y: pointer_relative_y - height / 2.0,
-            height: height,
-            width: width,
-        });
+    // Unmaps the overlay, giving back keyboard focus
+    fn hide(&mut self) {
+        if !self.hidden {
+            trace!("Hiding overlay");
+            self.surface.attach(None, 0, 0);
+            self.surface.commit();
+            self.hidden = true;
+        }
     }
-    pub fn cut_left(&mut self, x: f64) {
-        let rect = self.renderer.borrow().get_active_region();
-        self.renderer
-            .borrow_mut()
-            .update_active_region(cairo::Rectangle {
-                x: rect.x,
-                y: rect.y,
-                height: rect.height,
-                width: rect.width * x,
-            });
+    // Sends the window under the overlay what the key and type actions
+    // queued, then ends
+    fn send_typing(&mut self) {
+        let inputs = std::mem::take(&mut self.typing);
+        match (&mut self.virtual_keyboard, &self.seat) {
+            (Some(keyboard), Some(seat)) => {
+                if let Err(err) = keyboard.send(seat, &inputs) {
+                    warn!("Failed to send keys: {}", err);
+                }
+            }
+            _ => warn!(
+                "Compositor does not support zwp_virtual_keyboard_manager_v1, can't send {:?}",
+                inputs
+            ),
+        }
+        self.end();
     }
-    pub fn cut_down(&mut self, x: f64) {
-        let rect = self.renderer.borrow().get_active_region();
-        self.renderer
-            .borrow_mut()
-            .update_active_region(cairo::Rectangle {
-                x: rect.x,
-                y: rect.y + rect.height * (1.0 - x),
-                height: rect.height * x,
-                width: rect.width,
-            });
+    // Pixel density of the output our surface is on
+    fn pixels_per_mm(&self) -> f64 {
+        let outputs = self.outputs.borrow();
+        let info = self.current_output.and_then(|id| outputs.get(&id));
+        match info.map(|info| info.physical_size) {
+            Some((width, _)) if width > 0 => {
+                self.renderer.borrow().get_surface_size().0 as f64 / width as f64
+            }
+            _ => {
+                debug!("Physical size of output unknown, assuming 96dpi");
+                DEFAULT_PIXELS_PER_MM
+            }
+        }
     }
-
-    pub fn cut_up(&mut self, x: f64) {
-        let rect = self.renderer.borrow().get_active_region();
-        self.renderer
-            .borrow_mut()
-            .update_active_region(cairo::Rectangle {
-                x: rect.x,
-                y: rect.y,
-                height: rect.height * x,
-                width: rect.width,
-            });
+    // Position and size of the output our surface is on, in the compositor's
+    // global space
+    fn output_rect(&self) -> (i32, i32, i32, i32) {
+        let outputs = self.outputs.borrow();
+        let (x, y) = self
+            .current_output
+            .and_then(|id| outputs.get(&id))
+            .map_or((0, 0), |info| info.position);
+        let (width, height) = self.renderer.borrow().get_surface_size();
+        (x, y, width as i32, height as i32)
     }
-
-    pub fn cut_right(&mut self, x: f64) {
-        let rect = self.renderer.borrow_mut().get_active_region();
-        self.renderer
-            .borrow_mut()
-            .update_active_region(cairo::Rectangle {
-                x: rect.x + rect.width * (1.0 - x),
-                y: rect.y,
-                height: rect.height,
-                width: rect.width * x,
-            });
+    // Name of the output our surface is on, if the compositor told us. Empty
+    // until the surface entered an output, so that only the base settings
+    // apply until we know which output's do.
+    fn output_name(&self) -> String {
+        let outputs = self.outputs.borrow();
+        self.current_output
+            .and_then(|id| outputs.get(&id))
+            .map(|info| info.name.clone())
+            .unwrap_or_default()
     }
-
-    pub fn move_right(&mut self, x: f64) {
-        let rect = self.renderer.borrow().get_active_region();
-        self.renderer
-            .borrow_mut()
-            .update_active_region(cairo::Rectangle {
-                x: rect.x + rect.width * x,
-                y: rect.y,
-                height: rect.height,
-                width: rect.width,
-            });
+    // Like [App::output_name]
+    fn output_description(&self) -> String {
+        let outputs = self.outputs.borrow();
+        self.current_output
+            .and_then(|id| outputs.get(&id))
+            .map(|info| info.description.clone())
+            .unwrap_or_default()
     }
-    pub fn move_left(&mut self, x: f64) {
-        let rect = self.renderer.borrow().get_active_region();
-        self.renderer
-            .borrow_mut()
-            .update_active_region(cairo::Rectangle {
-                x: rect.x - rect.width * x,
-                y: rect.y,
-                height: rect.height,
-                width: rect.width,
-            });
+    // Switches to the settings for the output we are on, which until the
+    // first binding runs also decide where the region starts
+    fn apply_output_settings(&mut self) {
+        let (settings, sections) = match &self.config {
+            Left(config) => (&config.settings, &config.outputs),
+            Right(config) => (&config.settings, &config.outputs),
+        };
+        let (name, description) = (self.output_name(), self.output_description());
+        let settings = settings.for_output(sections, &name, &description);
+        trace!("Settings for output {}: {:?}", name, settings);
+        {
+            let mut renderer = self.renderer.borrow_mut();
+            renderer.set_animation(settings.animation_duration, settings.animation_easing);
+            renderer.set_colors(settings.fill_color, settings.border_color);
+        }
+        self.navigator.set_settings(settings);
+        self.reset_region();
     }
-    pub fn move_up(&mut self, x: f64) {
-        let rect = self.renderer.borrow().get_active_region();
-        self.renderer
-            .borrow_mut()
-            .update_active_region(cairo::Rectangle {
-                x: rect.x,
-                y: rect.y - rect.height * x,
-                height: rect.height,
-                width: rect.width,
-            });
+    // Draws at the scale of the output we are on, so that lines stay sharp
+    fn apply_output_scale(&mut self) {
+        let scale = {
+            let outputs = self.outputs.borrow();
+            self.current_output
+                .and_then(|id| outputs.get(&id))
+                .map_or(1, |info| info.scale.max(1))
+        };
+        if scale as u32 == self.renderer.borrow().get_scale() {
+            return;
+        }
+        trace!("Drawing at scale {}", scale);
+        self.renderer.borrow_mut().set_scale(scale as u32).unwrap();
+        self.surface.set_buffer_scale(scale);
+        self.rebind();
     }
-    pub fn move_down(&mut self, x: f64) {
-        let rect = self.renderer.borrow().get_active_region();
-        self.renderer
-            .borrow_mut()
-            .update_active_region(cairo::Rectangle {
-                x: rect.x,
-                y: rect.y + rect.height * x,
-                height: rect.height,
-                width: rect.width,
-            });
+    // Puts the region where initial-region says, unless the user moved it
+    // already
+    fn reset_region(&mut self) {
+        if !self.acted {
+            let remembered = match self.navigator.settings().initial_region {
+                InitialRegion::Last => self
+                    .last_regions
+                    .for_output(&self.output_name())
+                    .get(LAST_REGION)
+                    .copied(),
+                InitialRegion::FocusedWindow => self.focused_window_region(),
+                _ => None,
+            };
+            self.navigator.set_pixels_per_mm(self.pixels_per_mm());
+            self.navigator.reset_region(remembered);
+            self.renderer
+                .borrow_mut()
+                .jump_to_region(self.navigator.region());
+        }
+    }
+    // Capture of the output our surface is on
+    fn capture(&self) -> Option<&Capture> {
+        self.current_output.and_then(|id| self.captures.get(&id))
     }
     fn handle_keymap(&mut self, keymap: xkb::Keymap) {
         self.keyboard_state = Some(xkb::State::new(&keymap));
         // TODO: temporary, remove this
         let mut mappings = HashMap::<(xkb::ModMask, xkb::Keysym), Vec<KeynavAction>>::new();
-        for (key, val) in self
+        let raw_config = self
             .config
             .clone()
             .right()
-            .expect("config should be RawConfig before keymap is recieved")
-            .mappings
-        {
+            .expect("config should be RawConfig before keymap is recieved");
+        let profile = self
+            .focused_window()
+            .and_then(|window| raw_config.profile_for(&window.app_id, &window.title));
+        match profile {
+            Some(profile) => info!("Using profile [{}]", profile.window),
+            None => debug!("No profile matches the focused window"),
+        }
+        self.renderer
+            .borrow_mut()
+            .set_profile(profile.map(|profile| profile.window.to_string()));
+        for (key, val) in raw_config.layered_mappings(profile) {
             let mut modmask = 0;
             // TODO: This will not give good errors if for example a mapping
             // has two keysyms and the last one is invalid. Also, the whole
@@ -444,7 +932,7 @@ impl App {
                     );
                 } else {
                     trace!("Normal key: {}", component);
-                    keysym = xkb::keysym_from_name(&component, xkb::KEYSYM_NO_FLAGS);
+                    keysym = xkb::keysym_from_name(component, xkb::KEYSYM_NO_FLAGS);
                     if keysym == xkb::KEY_NoSymbol {
                         panic!("Key not mapped");
                     }
@@ -458,77 +946,334 @@ impl App {
             trace!("Key, mask: {}, {}", keysym, modmask);
             mappings.insert((modmask, keysym), val.clone());
         }
-        self.config = Left(Config { mappings });
+        self.config = Left(Config {
+            mappings,
+            settings: raw_config.settings,
+            outputs: raw_config.outputs,
+        });
     }
 
-    fn execute_action(
-        &mut self,
-        action: &KeynavAction,
-        virtual_pointer: &Main<zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1>,
-    ) {
-        match action.clone() {
-            KeynavAction::CursorZoom { width, height } => {
-                trace!("Executing CenterCursor action");
-                self.cursor_zoom(width, height);
+    fn print(&mut self, text: &str) {
+        if let Err(err) = writeln!(self.stdout, "{}", text) {
+            warn!("Failed to print: {}", err);
+        }
+    }
+    fn copy(&self, text: String) {
+        match &self.clipboard {
+            Some(clipboard) => clipboard.copy(text, self.serial),
+            None => warn!("Can't copy {}, there is no clipboard", text),
+        }
+    }
+
+    fn run_command(&mut self, command: Command, pointer: &dyn PointerBackend) {
+        trace!("Running {:?}", command);
+        match command {
+            Command::Warp { x, y } => pointer.warp(x, y, self.output_rect()),
+            Command::Button { button, state } => pointer.button(button, state),
+            Command::ToggleMagnifier => {
+                let mut renderer = self.renderer.borrow_mut();
+                if renderer.is_magnifying() {
+                    renderer.set_magnifier(None);
+                } else {
+                    match self.capture().map(Capture::to_surface) {
+                        Some(Ok(surface)) => renderer.set_magnifier(Some(surface)),
+                        Some(Err(err)) => warn!("Failed to magnify: {}", err),
+                        None => warn!("Nothing to magnify, the screen was not captured"),
+                    }
+                }
             }
-            KeynavAction::CutRight(x) => {
-                trace!("Executing CutRight action");
-                self.cut_right(x.unwrap_or(0.5));
+            Command::PickColor {
+                region,
+                format,
+                average,
+                copy,
+            } => match self.capture() {
+                Some(capture) => {
+                    let colour = format.format(capture.sample(region, average));
+                    if copy {
+                        self.copy(colour);
+                    } else {
+                        self.print(&colour);
+                    }
+                }
+                None => warn!("Can't pick a colour, the screen was not captured"),
+            },
+            Command::Print(text) => self.print(&text),
+            Command::Key(combo) => self.typing.push(Input::Key(combo)),
+            Command::Type(text) => self.typing.push(Input::Text(text)),
+            Command::Sleep(duration) => {
+                // So that what came before happens before the wait, rather
+                // than all at once after it
+                if let Err(err) = self.display.flush() {
+                    warn!("Failed to flush requests to the compositor: {}", err);
+                }
+                if duration > MAX_SLEEP {
+                    warn!("Sleeping for {:?} instead of {:?}", MAX_SLEEP, duration);
+                }
+                self.resume_at = Some(Instant::now() + duration.min(MAX_SLEEP));
             }
-            KeynavAction::CutLeft(x) => {
-                trace!("Executing CutLeft action");
-                self.cut_left(x.unwrap_or(0.5));
+            Command::Copy(text) => self.copy(text),
+            Command::Screenshot { region, path } => match self.capture() {
+                Some(capture) => {
+                    match screenshot::expand_path(&path, screenshot::now())
+                        .and_then(|path| screenshot::save(capture, region, &path).map(|_| path))
+                    {
+                        Ok(path) => info!("Saved screenshot to {}", path.display()),
+                        Err(err) => warn!("Failed to save screenshot: {}", err),
+                    }
+                }
+                None => warn!("Can't take a screenshot, the screen was not captured"),
+            },
+            Command::SelectWindow => self.select_window(),
+            Command::Mark { name, region } => {
+                self.marks.set(&self.output_name(), &name, region);
+                if let Some(path) = &self.marks_path {
+                    match self.marks.save(path) {
+                        Ok(()) => debug!("Saved mark {} to {}", name, path.display()),
+                        Err(err) => warn!("Failed to save mark {}: {}", name, err),
+                    }
+                }
             }
-            KeynavAction::CutUp(x) => {
-                trace!("Executing CutUp action");
-                self.cut_up(x.unwrap_or(0.5));
+            Command::End => {
+                self.save_last_region();
+                self.end()
             }
-            KeynavAction::CutDown(x) => {
-                trace!("Executing CutDown action");
-                self.cut_down(x.unwrap_or(0.5));
+        }
+    }
+    // For initial-region last, which is all it is saved for
+    fn save_last_region(&mut self) {
+        if self.navigator.settings().initial_region != InitialRegion::Last {
+            return;
+        }
+        self.last_regions
+            .set(&self.output_name(), LAST_REGION, self.navigator.region());
+        if let Some(path) = &self.last_regions_path {
+            if let Err(err) = self.last_regions.save(path) {
+                warn!("Failed to save the region: {}", err);
             }
-            KeynavAction::MoveRight(x) => {
-                trace!("Executing MoveRight action");
-                self.move_right(x.unwrap_or(1.0));
+        }
+    }
+    // Where the focused window is on our output, if sway tells us
+    fn focused_window_region(&self) -> Option<cairo::Rectangle> {
+        match sway::windows() {
+            Ok(windows) => windows
+                .into_iter()
+                .find(|window| window.focused)
+                .and_then(|window| windows::region_on_output(window.rect, self.output_rect())),
+            Err(err) => {
+                warn!("Can't find the focused window: {}", err);
+                None
             }
-            KeynavAction::MoveLeft(x) => {
-                trace!("Executing MoveLeft action");
-                self.move_left(x.unwrap_or(1.0));
+        }
+    }
+
+    // The window that had focus before we showed up
+    fn focused_window(&self) -> Option<windows::Window> {
+        let focused = self.toplevels.as_ref().and_then(|toplevels| {
+            toplevels
+                .windows()
+                .into_iter()
+                // The fallback toplevel is a window too
+                .find(|window| window.activated && window.app_id != APP_ID)
+        });
+        focused.or_else(|| match sway::windows() {
+            Ok(windows) => windows
+                .into_iter()
+                .find(|window| window.focused)
+                .map(|window| windows::Window {
+                    title: window.title,
+                    app_id: window.app_id,
+                    activated: true,
+                    ..windows::Window::default()
+                }),
+            Err(err) => {
+                debug!("Can't ask sway for the focused window: {}", err);
+                None
             }
-            KeynavAction::MoveUp(x) => {
-                trace!("Executing MoveUp action");
-                self.move_up(x.unwrap_or(1.0));
+        })
+    }
+    // Lists the open windows, see [App::handle_picker_key] for choosing one
+    fn select_window(&mut self) {
+        let windows: Vec<_> = match &self.toplevels {
+            // The fallback toplevel is a window too
+            Some(toplevels) => toplevels
+                .windows()
+                .into_iter()
+                .filter(|window| window.app_id != APP_ID)
+                .collect(),
+            None => {
+                warn!("Compositor does not support zwlr_foreign_toplevel_manager_v1, can't list windows");
+                return;
             }
-            KeynavAction::MoveDown(x) => {
-                trace!("Executing MoveDown action");
-                self.move_down(x.unwrap_or(1.0));
+        };
+        if windows.is_empty() {
+            info!("There are no windows to select");
+            return;
+        }
+        // foreign-toplevel doesn't say where windows are, but sway will
+        let mut sway_windows = sway::windows().unwrap_or_else(|err| {
+            debug!("Can't get window geometry from sway: {}", err);
+            Vec::new()
+        });
+        let output = self.output_rect();
+        let items = windows
+            .iter()
+            .map(|window| {
+                let region = sway_windows
+                    .iter()
+                    .position(|sway_window| {
+                        sway_window.app_id == window.app_id && sway_window.title == window.title
+                    })
+                    .and_then(|position| {
+                        windows::region_on_output(sway_windows.remove(position).rect, output)
+                    });
+                (window.id, region)
+            })
+            .collect();
+        let picker = Picker::new(items);
+        self.show_picker(&picker);
+        self.picker = Some(picker);
+    }
+    fn show_picker(&self, picker: &Picker<(u64, Option<cairo::Rectangle>)>) {
+        let windows = self
+            .toplevels
+            .as_ref()
+            .map_or_else(Vec::new, Toplevels::windows);
+        let entries = picker
+            .candidates()
+            .filter_map(|(label, (id, _))| {
+                let window = windows.iter().find(|window| window.id == *id)?;
+                let description = if window.app_id.is_empty() {
+                    window.title.clone()
+                } else {
+                    format!("{} — {}", window.title, window.app_id)
+                };
+                Some((label.clone(), description))
+            })
+            .collect();
+        self.renderer.borrow_mut().set_window_list(Some(entries));
+    }
+    // Keys go to the picker instead of the mappings while it is open
+    fn handle_picker_key(&mut self, keysym: xkb::Keysym) {
+        let mut picker = match self.picker.take() {
+            Some(picker) => picker,
+            None => return,
+        };
+        if keysym == xkb::KEY_Escape {
+            trace!("Cancelled window selection");
+            self.renderer.borrow_mut().set_window_list(None);
+            return;
+        }
+        let c = match xkb::keysym_to_utf8(keysym)
+            .trim_end_matches('\0')
+            .chars()
+            .next()
+        {
+            Some(c) => c,
+            None => {
+                self.picker = Some(picker);
+                return;
             }
-            KeynavAction::Click(x) => {
-                trace!("Executing click action");
-                self.click(&virtual_pointer, x.unwrap_or(MouseButton::Left).to_code());
+        };
+        match picker.type_char(c) {
+            Typed::Chosen(&(id, region)) => {
+                if let (Some(toplevels), Some(seat)) = (&self.toplevels, &self.seat) {
+                    toplevels.activate(id, seat);
+                }
+                if let Some(region) = region {
+                    self.navigator.set_region(region);
+                    self.renderer
+                        .borrow_mut()
+                        .update_active_region(self.navigator.region());
+                }
+                self.renderer.borrow_mut().set_window_list(None);
             }
-            KeynavAction::DragButton(x) => {
-                trace!("Executing drag button action");
-                self.drag(&virtual_pointer, x.to_code());
+            Typed::Partial => {
+                self.show_picker(&picker);
+                self.picker = Some(picker);
             }
-            KeynavAction::DoubleClick(x) => {
-                trace!("Executing double click action");
-                self.double_click(&virtual_pointer, x.unwrap_or(MouseButton::Left).to_code());
+            Typed::NoMatch => {
+                trace!("No window is labelled with {:?}", c);
+                self.picker = Some(picker);
             }
-            KeynavAction::Warp => {
-                trace!("Executing warp action");
-                self.warp(&virtual_pointer);
+        }
+    }
+
+    // Runs the actions bound to a key, as many times as the count typed before
+    // it says. `repeat` stands for the actions that ran last.
+    fn run_binding(&mut self, actions: &[KeynavAction], pointer: &dyn PointerBackend) {
+        let count = self.count.take();
+        let (last_actions, last_count) = self.last_binding.clone();
+        let (actions, count) = if actions.iter().all(|action| *action == KeynavAction::Repeat) {
+            // Like in vim a new count replaces the old one
+            (last_actions, count.unwrap_or(last_count))
+        } else {
+            let actions: Vec<KeynavAction> = actions
+                .iter()
+                .flat_map(|action| match action {
+                    KeynavAction::Repeat => last_actions.clone(),
+                    action => vec![action.clone()],
+                })
+                .collect();
+            let count = count.unwrap_or(1);
+            self.last_binding = (actions.clone(), count);
+            (actions, count)
+        };
+        self.acted = true;
+        self.navigator.set_pixels_per_mm(self.pixels_per_mm());
+        self.navigator.set_output_name(&self.output_name());
+        let (output_x, output_y, _, _) = self.output_rect();
+        self.navigator.set_output_position(output_x, output_y);
+        self.navigator
+            .set_marks(self.marks.for_output(&self.output_name()));
+        for _ in 0..count {
+            let commands = self.navigator.execute(&actions);
+            let ends = commands.contains(&Command::End);
+            self.pending.extend(commands);
+            if ends {
+                break;
             }
-            KeynavAction::End => {
-                trace!("Executing end action");
-                self.end();
+        }
+        self.run_pending(pointer);
+        self.renderer
+            .borrow_mut()
+            .update_active_region(self.navigator.region());
+    }
+    // Runs the commands of the last binding up to the next sleep, after which
+    // [AppRunner::pump] resumes them
+    fn run_pending(&mut self, pointer: &dyn PointerBackend) {
+        self.resume_at = None;
+        while let Some(command) = self.pending.pop_front() {
+            self.run_command(command, pointer);
+            if self.resume_at.is_some() {
+                return;
             }
         }
+        if self.typing.is_empty() {
+            return;
+        }
+        // The window under the overlay only gets keyboard focus back once it
+        // is gone, which the keyboard leave event tells us about
+        self.hide();
+        if self.keyboard_focused {
+            self.resume_at = Some(Instant::now() + FOCUS_TIMEOUT);
+        } else {
+            self.send_typing();
+        }
+    }
+    fn resume(&mut self) {
+        if self.pending.is_empty() && !self.typing.is_empty() {
+            warn!("Still have keyboard focus after hiding, typing anyway");
+            self.resume_at = None;
+            self.send_typing();
+        } else if let Some(pointer) = self.pointer.clone() {
+            self.run_pending(&*pointer);
+        }
     }
 
     pub fn handle_keyboard_event(
         &mut self,
-        virtual_pointer: &Main<zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1>,
+        pointer: &dyn PointerBackend,
         event: wl_keyboard::Event,
     ) {
         match event {
@@ -545,9 +1290,8 @@ impl App {
                                 xkb::COMPILE_NO_FLAGS,
                             )
                         };
-                        match maybe_keymap_or_err {
-                            Ok(Some(keymap)) => self.handle_keymap(keymap),
-                            _ => {}
+                        if let Ok(Some(keymap)) = maybe_keymap_or_err {
+                            self.handle_keymap(keymap);
                         }
                     }
 
@@ -555,11 +1299,18 @@ impl App {
                     _ => {}
                 }
             }
-            wl_keyboard::Event::Enter { .. } => {
+            wl_keyboard::Event::Enter { serial, .. } => {
                 trace!("Gained keyboard focus.");
+                self.serial = serial;
+                self.keyboard_focused = true;
             }
             wl_keyboard::Event::Leave { .. } => {
                 trace!("Lost keyboard focus.");
+                self.keyboard_focused = false;
+                if self.hidden && self.pending.is_empty() && !self.typing.is_empty() {
+                    self.resume_at = None;
+                    self.send_typing();
+                }
             }
             wl_keyboard::Event::Modifiers {
                 mods_depressed,
@@ -569,27 +1320,27 @@ impl App {
                 ..
             } => {
                 // Lots of xkbcommon stuff sanity checked against wev
-                match self.keyboard_state.clone() {
-                    Some(mut keyboard_state) => {
-                        keyboard_state.update_mask(
-                            mods_depressed,
-                            mods_latched,
-                            mods_locked,
-                            0,
-                            0,
-                            group,
-                        );
-                    }
-                    None => {}
-                };
+                if let Some(mut keyboard_state) = self.keyboard_state.clone() {
+                    keyboard_state.update_mask(
+                        mods_depressed,
+                        mods_latched,
+                        mods_locked,
+                        0,
+                        0,
+                        group,
+                    );
+                }
             }
-            wl_keyboard::Event::Key { key, state, .. } => {
+            wl_keyboard::Event::Key {
+                key, state, serial, ..
+            } => {
                 trace!("Key with id {} was {:?}.", key, state);
+                self.serial = serial;
                 // TODO: Learn how xkbcommon actually works?
                 let (modmask, key) = match self.keyboard_state.clone() {
                     Some(mut keyboard_state) => {
                         // Docs suggest getting key before updating
-                        let keysym = keyboard_state
+                        let keysym = *keyboard_state
                             .get_keymap()
                             .key_get_syms_by_level(
                                 key + 8,
@@ -597,8 +1348,7 @@ impl App {
                                 0,
                             )
                             .first()
-                            .expect("there to be at least one keysym")
-                            .clone();
+                            .expect("there to be at least one keysym");
                         trace!("Key maps to {}", key);
                         keyboard_state.update_key(
                             key + 8, /* wayland docs told me to? */
@@ -609,7 +1359,8 @@ impl App {
                             },
                         );
                         (
-                            keyboard_state.serialize_mods(xkb::STATE_MODS_EFFECTIVE),
+                            keyboard_state.serialize_mods(xkb::STATE_MODS_EFFECTIVE)
+                                & !lock_modifiers(&keyboard_state.get_keymap()),
                             keysym,
                         )
                     }
@@ -617,23 +1368,41 @@ impl App {
                 };
                 trace!("Modmask: {}", modmask);
                 // TODO: Maybe handle press vs relase
-                if state == wl_keyboard::KeyState::Pressed {
-                    if let Left(Config { mappings }) = &self.config {
+                if state == wl_keyboard::KeyState::Pressed && self.resume_at.is_some() {
+                    trace!("Ignoring key while sleeping");
+                    return;
+                }
+                // 0 only counts after another digit, so it can still be bound
+                let digit = (xkb::KEY_0..=xkb::KEY_9)
+                    .contains(&key)
+                    .then(|| key - xkb::KEY_0)
+                    .filter(|digit| *digit != 0 || self.count.is_some());
+                if state == wl_keyboard::KeyState::Pressed && self.picker.is_some() {
+                    self.handle_picker_key(key);
+                } else if state == wl_keyboard::KeyState::Pressed
+                    && modmask == 0
+                    && digit.is_some()
+                    && self.navigator.settings().count_keys
+                {
+                    let count = (self.count.unwrap_or(0) * 10 + digit.unwrap()).min(MAX_COUNT);
+                    trace!("Count is {}", count);
+                    self.count = Some(count);
+                } else if state == wl_keyboard::KeyState::Pressed {
+                    if let Left(Config { mappings, .. }) = &self.config {
                         let mappings = &mappings.clone();
                         match mappings.get(&(modmask, key)) {
-                            Some(actions) => {
-                                actions
-                                    .iter()
-                                    .for_each(|action| self.execute_action(action, virtual_pointer));
-                            }
+                            Some(actions) => self.run_binding(actions, pointer),
+                            // Modifiers are pressed on the way to a binding,
+                            // which the count is still for
+                            None if is_modifier(key) => (),
                             None => {
-                                trace!("No actions associated with key")
+                                trace!("No actions associated with key");
+                                // Like in vim a count doesn't outlive a typo
+                                self.count = None;
                             }
                         }
                     }
                 }
-                self.renderer.borrow_mut().redraw().unwrap();
-                self.commit();
             }
             _ => (),
         }
@@ -647,29 +1416,133 @@ pub struct AppRunner {
 impl AppRunner {
     pub fn init(config: RawConfig) -> Result<Self, String> {
         trace!("Connecting to server");
-        let display = Display::connect_to_env().unwrap();
+        let display = Display::connect_to_env().map_err(|err| err.to_string())?;
 
+        Self::init_with_display(display, config)
+    }
+    // Useful for connecting over a socket that isn't in the environment, eg.
+    // to a test compositor
+    pub fn init_with_display(display: Display, config: RawConfig) -> Result<Self, String> {
         let mut event_queue = display.create_event_queue();
 
         let app = App::init(config, &mut event_queue)?;
 
-        Ok(AppRunner {
-            app: app,
-            event_queue,
-        })
+        Ok(AppRunner { app, event_queue })
     }
     pub fn pump(&mut self) -> bool {
+        let resume_at = self.app.borrow().resume_at;
+        match resume_at {
+            Some(deadline) => {
+                self.dispatch_until(deadline);
+                if Instant::now() >= deadline {
+                    self.app.borrow_mut().resume();
+                }
+            }
+            None => {
+                self.event_queue
+                    .dispatch(&mut (), |_, _, _| { /* we ignore unfiltered messages */ })
+                    .unwrap();
+            }
+        }
+        let should_end = self.app.borrow().should_end;
+        let serving = self.app.borrow().is_serving_clipboard();
+        // An `end` can come before the rest of its binding ran, eg. the keys
+        // waiting for the overlay to lose keyboard focus
+        let waiting = self.app.borrow().resume_at.is_some();
+        if !should_end {
+            // Show what the events changed, or what piled up while we were
+            // waiting for a frame callback
+            self.app.borrow_mut().commit();
+        } else if serving || waiting {
+            self.app.borrow_mut().hide();
+        }
+        // Send whatever the events made us do (eg. clicks) now rather than on
+        // the next dispatch, which never comes if we are ending
+        if let Err(err) = self.event_queue.display().flush() {
+            warn!("Failed to flush requests to the compositor: {}", err);
+        }
+        // TODO: Should I relase buttons if they are pressed here?
+        !should_end || serving || waiting
+    }
+    // Like [EventQueue::dispatch], but only waits for events until `deadline`
+    fn dispatch_until(&mut self, deadline: Instant) {
         self.event_queue
-            .dispatch(&mut (), |_, _, _| { /* we ignore unfiltered messages */ })
+            .dispatch_pending(&mut (), |_, _, _| { /* we ignore unfiltered messages */ })
             .unwrap();
-        if self.app.borrow().should_end {
-            // TODO: Should I relase buttons if they are pressed here?
-            self.event_queue
-                .dispatch(&mut (), |_, _, _| { /* we ignore unfiltered messages */ })
-                .unwrap();
-            false
-        } else {
-            true
+        if let Err(err) = self.event_queue.display().flush() {
+            warn!("Failed to flush requests to the compositor: {}", err);
         }
+        if let Some(guard) = self.event_queue.prepare_read() {
+            let mut fd = libc::pollfd {
+                fd: self.event_queue.display().get_connection_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // Rounded up, so that we don't wake up just before the deadline
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let timeout = (timeout.as_micros() as i32 + 999) / 1000;
+            if unsafe { libc::poll(&mut fd, 1, timeout) } > 0 {
+                if let Err(err) = guard.read_events() {
+                    warn!("Failed to read events from the compositor: {}", err);
+                }
+            }
+        }
+        self.event_queue
+            .dispatch_pending(&mut (), |_, _, _| { /* we ignore unfiltered messages */ })
+            .unwrap();
+    }
+    /// Have print and pick-color write to `stdout` instead of the real one
+    pub fn print_to(&mut self, stdout: Box<dyn Write>) {
+        self.app.borrow_mut().stdout = stdout;
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(dead_code)]
+    fn globals(interfaces: &[(&str, u32)]) -> Vec<(u32, String, u32)> {
+        interfaces
+            .iter()
+            .enumerate()
+            .map(|(id, (interface, version))| (id as u32, interface.to_string(), *version))
+            .collect()
+    }
+
+    #[test]
+    fn missing_protocols_are_listed() {
+        let core = [("wl_compositor", 4), ("wl_shm", 1), ("wl_seat", 7)];
+        let with_layer_shell = globals(&[&core[..], &[("zwlr_layer_shell_v1", 4)]].concat());
+        let with_xdg_shell = globals(&[&core[..], &[("xdg_wm_base", 5)]].concat());
+
+        assert_eq!(
+            Ok(()),
+            check_protocols(&with_layer_shell, SurfaceChoice::Auto)
+        );
+        assert_eq!(
+            Ok(()),
+            check_protocols(&with_xdg_shell, SurfaceChoice::Auto)
+        );
+        assert_eq!(
+            Err("Compositor does not support these required protocols: \
+                 zwlr_layer_shell_v1 (version 4)"
+                .into()),
+            check_protocols(&with_xdg_shell, SurfaceChoice::LayerShell)
+        );
+        assert_eq!(
+            Err("Compositor does not support these required protocols: \
+                 wl_compositor (version 4), wl_seat, \
+                 zwlr_layer_shell_v1 (version 4) or xdg_wm_base"
+                .into()),
+            check_protocols(
+                &globals(&[
+                    ("wl_compositor", 3),
+                    ("wl_shm", 1),
+                    ("zwlr_layer_shell_v1", 3)
+                ]),
+                SurfaceChoice::Auto
+            )
+        );
     }
 }
@@ -0,0 +1,137 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::rc::Rc;
+
+use log::*;
+
+use wayland_client::{
+    protocol::{wl_data_device, wl_data_device_manager, wl_data_source, wl_seat},
+    Main,
+};
+use wayland_protocols::wlr::unstable::data_control::v1::client::{
+    zwlr_data_control_device_v1, zwlr_data_control_manager_v1, zwlr_data_control_source_v1,
+};
+
+// What we offer our selections as
+const MIME_TYPES: [&str; 4] = [
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+];
+
+enum Device {
+    // The regular clipboard, which compositors may only let us use while we
+    // have focus
+    DataDevice {
+        manager: Main<wl_data_device_manager::WlDataDeviceManager>,
+        device: Main<wl_data_device::WlDataDevice>,
+    },
+    // For clipboard managers, works regardless of focus
+    DataControl {
+        manager: Main<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1>,
+        device: Main<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1>,
+    },
+}
+
+/// Puts text on the clipboard. Wayland clipboards are served by whoever set
+/// the selection, so we have to stay around until someone else replaces it.
+pub struct Clipboard {
+    device: Device,
+    // Selections we set that haven't been replaced yet
+    serving: Rc<Cell<u32>>,
+}
+
+impl Clipboard {
+    pub fn with_data_device(
+        manager: Main<wl_data_device_manager::WlDataDeviceManager>,
+        seat: &wl_seat::WlSeat,
+    ) -> Self {
+        let device = manager.get_data_device(seat);
+        device.quick_assign(|_, event, _| {
+            // We don't read the clipboard, so let go of what others offer
+            if let wl_data_device::Event::Selection { id: Some(offer) } = event {
+                offer.destroy();
+            }
+        });
+        Clipboard {
+            device: Device::DataDevice { manager, device },
+            serving: Rc::new(Cell::new(0)),
+        }
+    }
+
+    pub fn with_data_control(
+        manager: Main<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1>,
+        seat: &wl_seat::WlSeat,
+    ) -> Self {
+        let device = manager.get_data_device(seat);
+        device.quick_assign(|_, event, _| {
+            if let zwlr_data_control_device_v1::Event::Selection { id: Some(offer) } = event {
+                offer.destroy();
+            }
+        });
+        Clipboard {
+            device: Device::DataControl { manager, device },
+            serving: Rc::new(Cell::new(0)),
+        }
+    }
+
+    /// Offers `text` as the selection. `serial` has to be that of a recent
+    /// input event, or the compositor may ignore us.
+    pub fn copy(&self, text: String, serial: u32) {
+        let serving = self.serving.clone();
+        match &self.device {
+            Device::DataDevice { manager, device } => {
+                let source = manager.create_data_source();
+                for mime_type in MIME_TYPES {
+                    source.offer(mime_type.into());
+                }
+                source.quick_assign(move |source, event, _| match event {
+                    wl_data_source::Event::Send { mime_type, fd } => send(&text, &mime_type, fd),
+                    wl_data_source::Event::Cancelled => {
+                        trace!("Selection was replaced");
+                        source.destroy();
+                        serving.set(serving.get() - 1);
+                    }
+                    _ => (),
+                });
+                device.set_selection(Some(&source), serial);
+            }
+            Device::DataControl { manager, device } => {
+                let source = manager.create_data_source();
+                for mime_type in MIME_TYPES {
+                    source.offer(mime_type.into());
+                }
+                source.quick_assign(move |source, event, _| match event {
+                    zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                        send(&text, &mime_type, fd)
+                    }
+                    zwlr_data_control_source_v1::Event::Cancelled => {
+                        trace!("Selection was replaced");
+                        source.destroy();
+                        serving.set(serving.get() - 1);
+                    }
+                    _ => (),
+                });
+                device.set_selection(Some(&source));
+            }
+        }
+        self.serving.set(self.serving.get() + 1);
+    }
+
+    /// Whether we still have to answer requests for our selection
+    pub fn is_serving(&self) -> bool {
+        self.serving.get() > 0
+    }
+}
+
+// Answers a request for the selection, taking ownership of `fd`
+fn send(text: &str, mime_type: &str, fd: RawFd) {
+    trace!("Sending selection as {}", mime_type);
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    if let Err(err) = file.write_all(text.as_bytes()) {
+        warn!("Failed to send selection: {}", err);
+    }
+}
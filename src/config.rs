@@ -4,6 +4,7 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
+use std::time::Duration;
 use std::vec::Vec;
 
 use xkbcommon::xkb::*;
@@ -13,6 +14,11 @@ pub enum MouseButton {
     Left,
     Right,
     Middle,
+    Side,
+    Extra,
+    Forward,
+    Back,
+    Task,
     // TODO: wheel?
 }
 
@@ -23,17 +29,351 @@ impl MouseButton {
             Self::Left => 0x110,
             Self::Right => 0x111,
             Self::Middle => 0x112,
+            Self::Side => 0x113,
+            Self::Extra => 0x114,
+            Self::Forward => 0x115,
+            Self::Back => 0x116,
+            Self::Task => 0x117,
         }
     }
 
+    /// Parses a button name, or a number in the given numbering
+    pub fn parse(s: &str, numbering: ButtonNumbering) -> Result<Self, String> {
+        match s {
+            "left" => return Ok(Self::Left),
+            "right" => return Ok(Self::Right),
+            "middle" => return Ok(Self::Middle),
+            "side" => return Ok(Self::Side),
+            "extra" => return Ok(Self::Extra),
+            // What mice's thumb buttons send, rather than the BTN_BACK and
+            // BTN_FORWARD their names suggest, which get names of their own
+            "back" => return Ok(Self::Side),
+            "forward" => return Ok(Self::Extra),
+            "btn-back" => return Ok(Self::Back),
+            "btn-forward" => return Ok(Self::Forward),
+            "task" => return Ok(Self::Task),
+            _ => {}
+        }
+        let number = s.parse::<u32>().map_err(|_| {
+            format!(
+                "'{}' is not a button number or one of left, right, middle, side, extra, \
+                 back, forward, btn-back, btn-forward or task",
+                s
+            )
+        })?;
+        match (numbering, number) {
+            (ButtonNumbering::Evdev, 1) => Ok(Self::Left),
+            (ButtonNumbering::Evdev, 2) => Ok(Self::Right),
+            (ButtonNumbering::Evdev, 3) => Ok(Self::Middle),
+            (ButtonNumbering::Evdev, 4) => Ok(Self::Side),
+            (ButtonNumbering::Evdev, 5) => Ok(Self::Extra),
+            (ButtonNumbering::Evdev, 6) => Ok(Self::Forward),
+            (ButtonNumbering::Evdev, 7) => Ok(Self::Back),
+            (ButtonNumbering::Evdev, 8) => Ok(Self::Task),
+            (ButtonNumbering::X11, 1) => Ok(Self::Left),
+            (ButtonNumbering::X11, 2) => Ok(Self::Middle),
+            (ButtonNumbering::X11, 3) => Ok(Self::Right),
+            // What the X evdev and libinput drivers turn BTN_SIDE and
+            // BTN_EXTRA into
+            (ButtonNumbering::X11, 8) => Ok(Self::Side),
+            (ButtonNumbering::X11, 9) => Ok(Self::Extra),
+            _ => Err(format!(
+                "There is no button {} in {}",
+                number,
+                numbering.describe()
+            )),
+        }
+    }
+}
+
+/// How button numbers in actions like `click 2` are read.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ButtonNumbering {
+    /// In the order of linux/input-event-code.h: 1 left, 2 right, 3 middle,
+    /// then side, extra, forward, back and task.
+    Evdev,
+    /// Like X11 and its keynav: 1 left, 2 middle, 3 right, 8 side and 9
+    /// extra.
+    X11,
+}
+
+impl ButtonNumbering {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "evdev" => Ok(Self::Evdev),
+            "x11" => Ok(Self::X11),
+            _ => Err(format!("'{}' is not one of evdev or x11", s)),
+        }
+    }
+    // Which number is which button, for error messages
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Evdev => {
+                "evdev numbering, where 1 is left, 2 right, 3 middle, 4 side, 5 extra, \
+                 6 btn-forward, 7 btn-back and 8 task"
+            }
+            Self::X11 => {
+                "x11 numbering, where 1 is left, 2 middle, 3 right, 8 side and 9 extra, \
+                 and 4 to 7 are the scroll wheel, which can't be clicked"
+            }
+        }
+    }
+}
+
+/// A distance argument to an action, eg. `0.5`, `10%`, `200px` or `20mm`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Length {
+    /// A fraction of whatever the action measures against (the active region
+    /// for cuts and moves, the whole surface for cursorzoom). Written either as
+    /// a bare number or as a percentage.
+    Fraction(f64),
+    Pixels(f64),
+    Millimeters(f64),
+}
+
+impl Length {
+    /// Parses a length where a bare number is a fraction.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::parse_with_default(s, Self::Fraction)
+    }
+
+    /// Parses a length where a bare number is a pixel count, for arguments
+    /// which have always been given in pixels (eg. cursorzoom).
+    pub fn parse_pixels(s: &str) -> Result<Self, String> {
+        Self::parse_with_default(s, Self::Pixels)
+    }
+
+    fn parse_with_default(s: &str, bare: fn(f64) -> Self) -> Result<Self, String> {
+        let (number, make): (&str, fn(f64) -> Self) = if let Some(v) = s.strip_suffix("px") {
+            (v, Self::Pixels)
+        } else if let Some(v) = s.strip_suffix("mm") {
+            (v, Self::Millimeters)
+        } else if let Some(v) = s.strip_suffix('%') {
+            (v, |x| Self::Fraction(x / 100.0))
+        } else {
+            (s, bare)
+        };
+        match number.parse::<f64>() {
+            Ok(v) if v.is_finite() && v >= 0.0 => Ok(make(v)),
+            Ok(_) => Err(format!("Length '{}' must be a non-negative number", s)),
+            Err(e) => Err(format!("Could not parse length '{}': {}", s, e)),
+        }
+    }
+
+    /// Converts to a fraction of `reference`, which is a length in surface
+    /// pixels. `pixels_per_mm` is the density of the output the surface is on.
+    pub fn to_fraction(&self, reference: f64, pixels_per_mm: f64) -> f64 {
+        match *self {
+            Self::Fraction(x) => x,
+            Self::Pixels(x) => x / reference,
+            Self::Millimeters(x) => x * pixels_per_mm / reference,
+        }
+    }
+}
+
+/// A named point on the edge or in the middle of the active region.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "top-left" => Ok(Self::TopLeft),
+            "top" => Ok(Self::Top),
+            "top-right" => Ok(Self::TopRight),
+            "left" => Ok(Self::Left),
+            "center" => Ok(Self::Center),
+            "right" => Ok(Self::Right),
+            "bottom-left" => Ok(Self::BottomLeft),
+            "bottom" => Ok(Self::Bottom),
+            "bottom-right" => Ok(Self::BottomRight),
+            _ => Err(format!("'{}' is not an anchor", s)),
+        }
+    }
+
+    /// Position of the anchor within the unit square.
+    pub fn offset(&self) -> (f64, f64) {
+        match self {
+            Self::TopLeft => (0.0, 0.0),
+            Self::Top => (0.5, 0.0),
+            Self::TopRight => (1.0, 0.0),
+            Self::Left => (0.0, 0.5),
+            Self::Center => (0.5, 0.5),
+            Self::Right => (1.0, 0.5),
+            Self::BottomLeft => (0.0, 1.0),
+            Self::Bottom => (0.5, 1.0),
+            Self::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// How a picked colour is written out.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorFormat {
+    /// `#rrggbb`
+    Hex,
+    /// `rgb(r, g, b)`
+    Rgb,
+    /// `hsl(h, s%, l%)`
+    Hsl,
+}
+
+impl ColorFormat {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "hex" => Ok(Self::Hex),
+            "rgb" => Ok(Self::Rgb),
+            "hsl" => Ok(Self::Hsl),
+            _ => Err(format!("'{}' is not one of hex, rgb or hsl", s)),
+        }
+    }
+
+    pub fn format(&self, (r, g, b): (u8, u8, u8)) -> String {
+        match self {
+            Self::Hex => format!("#{:02x}{:02x}{:02x}", r, g, b),
+            Self::Rgb => format!("rgb({}, {}, {})", r, g, b),
+            Self::Hsl => {
+                let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+                let max = r.max(g).max(b);
+                let min = r.min(g).min(b);
+                let lightness = (max + min) / 2.0;
+                let delta = max - min;
+                let (hue, saturation) = if delta == 0.0 {
+                    (0.0, 0.0)
+                } else {
+                    let hue = if max == r {
+                        ((g - b) / delta).rem_euclid(6.0)
+                    } else if max == g {
+                        (b - r) / delta + 2.0
+                    } else {
+                        (r - g) / delta + 4.0
+                    };
+                    (hue * 60.0, delta / (1.0 - (2.0 * lightness - 1.0).abs()))
+                };
+                format!(
+                    "hsl({}, {}%, {}%)",
+                    hue.round() as u32 % 360,
+                    (saturation * 100.0).round(),
+                    (lightness * 100.0).round()
+                )
+            }
+        }
+    }
+}
+
+/// A modifier held down for the `key` action.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Alt,
+    Super,
+}
+
+impl Modifier {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "shift" => Ok(Self::Shift),
+            "ctrl" | "control" => Ok(Self::Control),
+            "alt" | "mod1" => Ok(Self::Alt),
+            "super" | "logo" | "mod4" => Ok(Self::Super),
+            _ => Err(format!("'{}' is not one of shift, ctrl, alt or super", s)),
+        }
+    }
+
+    /// Its bit in xkb's modifier masks, where the real modifiers always come
+    /// in the same order
+    pub fn mask(&self) -> u32 {
+        match self {
+            Self::Shift => 1 << 0,
+            Self::Control => 1 << 2,
+            Self::Alt => 1 << 3,
+            Self::Super => 1 << 6,
+        }
+    }
+}
+
+/// A key with modifiers, eg. `ctrl+shift+t`, for the `key` action.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyCombo {
+    pub modifiers: Vec<Modifier>,
+    pub keysym: Keysym,
+}
+
+impl KeyCombo {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parts: Vec<&str> = s.split('+').collect();
+        let key = parts.pop().unwrap_or_default();
+        let modifiers = parts
+            .into_iter()
+            .map(Modifier::parse)
+            .collect::<Result<_, _>>()?;
+        let keysym = match keysym_from_name(key, KEYSYM_NO_FLAGS) {
+            KEY_NoSymbol => keysym_from_name(key, KEYSYM_CASE_INSENSITIVE),
+            keysym => keysym,
+        };
+        if keysym == KEY_NoSymbol {
+            return Err(format!("'{}' is not the name of a key", key));
+        }
+        Ok(Self { modifiers, keysym })
+    }
+}
+
+/// Text describing the active region, for print and copy. `%x`, `%y`, `%w`
+/// and `%h` are its position and size in pixels, `%X` and `%Y` its center,
+/// `%o` the name of the output and `%%` a literal `%`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TextFormat(String);
+
+impl TextFormat {
+    const PLACEHOLDERS: &'static str = "xywhXYo%";
+
+    /// Also accepts the shorthands `geometry` (`%x,%y %wx%h`, like slurp) and
+    /// `center` (`%X,%Y`).
     pub fn parse(s: &str) -> Result<Self, String> {
-        match s.parse::<u32>() {
-            Ok(1) => Ok(Self::Left),
-            Ok(2) => Ok(Self::Right),
-            Ok(3) => Ok(Self::Middle),
-            Ok(_) => Err("Value is out of range of MouseButton".into()),
-            Err(e) => Err(e.to_string()),
+        let format = match s {
+            "geometry" => "%x,%y %wx%h",
+            "center" => "%X,%Y",
+            s => s,
+        };
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c == '%' {
+                match chars.next() {
+                    Some(c) if Self::PLACEHOLDERS.contains(c) => {}
+                    Some(c) => return Err(format!("Unknown placeholder '%{}'", c)),
+                    None => return Err("Format ends in a lone '%'".into()),
+                }
+            }
+        }
+        Ok(TextFormat(format.to_string()))
+    }
+
+    /// Replaces every placeholder with `value(placeholder)`
+    pub fn expand(&self, value: impl Fn(char) -> String) -> String {
+        let mut expanded = String::new();
+        let mut chars = self.0.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' => match chars.next() {
+                    Some('%') => expanded.push('%'),
+                    Some(c) => expanded.push_str(&value(c)),
+                    None => (),
+                },
+                c => expanded.push(c),
+            }
         }
+        expanded
     }
 }
 
@@ -42,31 +382,435 @@ pub enum KeynavAction {
     // Cuts and moves
     // Note: windowzoom is not implemented as I don't know how to get
     // information about the "current application window" in wayland
-    CutRight(Option<f64>),
-    CutLeft(Option<f64>),
-    CutUp(Option<f64>),
-    CutDown(Option<f64>),
-    MoveRight(Option<f64>),
-    MoveLeft(Option<f64>),
-    MoveUp(Option<f64>),
-    MoveDown(Option<f64>),
-    CursorZoom { width: u32, height: u32 },
+    CutRight(Option<Length>),
+    CutLeft(Option<Length>),
+    CutUp(Option<Length>),
+    CutDown(Option<Length>),
+    MoveRight(Option<Length>),
+    MoveLeft(Option<Length>),
+    MoveUp(Option<Length>),
+    MoveDown(Option<Length>),
+    // Width and height, or the cursorzoom-size setting if None
+    CursorZoom(Option<(Length, Length)>),
+    // Absolute positioning, relative to the whole surface rather than the
+    // active region
+    Region {
+        x: Length,
+        y: Length,
+        width: Length,
+        height: Length,
+    },
+    // Centers the active region on (x, y)
+    MoveTo {
+        x: Length,
+        y: Length,
+    },
 
     // TODO: Grid commands
     // Mouse commands:
     Warp,
+    WarpTo(Anchor),
     Click(Option<MouseButton>),
     DoubleClick(Option<MouseButton>),
     // TODO: Add modifier keys
     DragButton(MouseButton),
+    // Remember the center of the active region as where to drag from, with
+    // the left button if None
+    DragStart(Option<MouseButton>),
+    // Drag from there to the center of the active region
+    DragEnd,
+    // Press the button, wait and release it
+    Hold(MouseButton, Duration),
+    // Wait before running the next action
+    Sleep(Duration),
+
+    // Overlay commands
+    // Toggles a magnified view of the screen under the active region
+    Magnify,
+    // Prints (or copies) the colour at the center of the active region, or the
+    // average colour of the region
+    PickColor {
+        format: ColorFormat,
+        average: bool,
+        copy: bool,
+    },
+    // Saves the screen under the active region as a PNG. The path may start
+    // with ~ and contain strftime patterns.
+    Screenshot(String),
+    // Presses a key in the window under the overlay, which ends first
+    Key(KeyCombo),
+    // Types text in the window under the overlay, which ends first
+    Type(String),
+    // Writes the geometry of the active region to stdout or the clipboard
+    Print(TextFormat),
+    Copy(TextFormat),
+    // Lists the open windows with a label each, activating the one whose
+    // label is typed
+    WindowSelect,
+    // Remembers the active region under a name, across restarts
+    Mark(String),
+    // Goes back to a marked region, and warps to its center if `warp`
+    GotoMark {
+        name: String,
+        warp: bool,
+    },
+    // Runs the actions of the last key again. Up to whoever handles the keys,
+    // the navigator ignores it.
+    Repeat,
 
     // TODO: Miscalenous commands
     End,
 }
 
+impl KeynavAction {
+    /// Whether the action needs to see what is on screen, meaning we have to
+    /// capture the output before covering it with the overlay
+    pub fn needs_screen_contents(&self) -> bool {
+        matches!(
+            self,
+            KeynavAction::Magnify | KeynavAction::PickColor { .. } | KeynavAction::Screenshot(_)
+        )
+    }
+}
+
+/// What to do when an action would push the active region past the edge of
+/// the screen.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EdgePolicy {
+    /// Keep the size of the region and slide it back inside.
+    Clamp,
+    /// Ignore moves that would leave the screen.
+    Stop,
+    /// Slide up to the edge, then move to the opposite edge on the next move.
+    Wrap,
+}
+
+impl EdgePolicy {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "clamp" => Ok(Self::Clamp),
+            "stop" => Ok(Self::Stop),
+            "wrap" => Ok(Self::Wrap),
+            _ => Err(format!("'{}' is not one of clamp, stop or wrap", s)),
+        }
+    }
+}
+
+/// How an animated region transition progresses over its duration.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Easing {
+    Linear,
+    /// Start fast and slow down towards the new region.
+    EaseOut,
+    /// Speed up, then slow down.
+    EaseInOut,
+}
+
+impl Easing {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "ease-out" => Ok(Self::EaseOut),
+            "ease-in-out" => Ok(Self::EaseInOut),
+            _ => Err(format!(
+                "'{}' is not one of linear, ease-out or ease-in-out",
+                s
+            )),
+        }
+    }
+    /// Maps the fraction of the duration that has passed to the fraction of
+    /// the way to the new region.
+    pub fn apply(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Self::EaseInOut if t < 0.5 => 4.0 * t.powi(3),
+            Self::EaseInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+        }
+    }
+}
+
+/// How clicks and pointer motion reach the compositor.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PointerBackendChoice {
+    /// The first one the compositor supports, in the order below.
+    Auto,
+    /// zwlr_virtual_pointer_v1 (sway, Hyprland, river, ...)
+    WlrVirtualPointer,
+    /// org_kde_kwin_fake_input (KWin)
+    KdeFakeInput,
+    /// Only log what would have been sent.
+    DryRun,
+}
+
+impl PointerBackendChoice {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "wlr-virtual-pointer" => Ok(Self::WlrVirtualPointer),
+            "kde-fake-input" => Ok(Self::KdeFakeInput),
+            "dry-run" => Ok(Self::DryRun),
+            _ => Err(format!(
+                "'{}' is not one of auto, wlr-virtual-pointer, kde-fake-input or dry-run",
+                s
+            )),
+        }
+    }
+}
+
+/// What kind of surface the overlay is shown on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SurfaceChoice {
+    /// A layer surface if the compositor supports it, else a toplevel.
+    Auto,
+    /// A zwlr_layer_surface_v1 above everything else.
+    LayerShell,
+    /// A fullscreen xdg_toplevel, for compositors without layer shell.
+    XdgShell,
+}
+
+impl SurfaceChoice {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "layer-shell" => Ok(Self::LayerShell),
+            "xdg-shell" => Ok(Self::XdgShell),
+            _ => Err(format!(
+                "'{}' is not one of auto, layer-shell or xdg-shell",
+                s
+            )),
+        }
+    }
+}
+
+/// A colour with alpha, written as `#rrggbb` or `#rrggbbaa`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Color {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+impl Color {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("'{}' is not a colour like #rrggbb or #rrggbbaa", s);
+        let hex = s.strip_prefix('#').ok_or_else(invalid)?;
+        if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map(|v| v as f64 / 255.0)
+                .map_err(|_| invalid())
+        };
+        Ok(Color {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+            a: if hex.len() == 8 { channel(6)? } else { 1.0 },
+        })
+    }
+}
+
+/// Where the active region starts out.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InitialRegion {
+    /// The whole surface
+    Full,
+    /// Where it was when keynav-wayland last ended on the same output
+    Last,
+    /// A cursorzoom of cursorzoom-size around the pointer
+    Cursor,
+    /// The window that has focus (only on sway)
+    FocusedWindow,
+    /// Like the `region` action
+    Fixed {
+        x: Length,
+        y: Length,
+        width: Length,
+        height: Length,
+    },
+}
+
+impl InitialRegion {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.split_whitespace().collect::<Vec<_>>()[..] {
+            ["full"] => Ok(Self::Full),
+            ["last"] => Ok(Self::Last),
+            ["cursor"] => Ok(Self::Cursor),
+            ["focused-window"] => Ok(Self::FocusedWindow),
+            [x, y, width, height] => Ok(Self::Fixed {
+                x: Length::parse(x)?,
+                y: Length::parse(y)?,
+                width: Length::parse(width)?,
+                height: Length::parse(height)?,
+            }),
+            _ => Err(format!(
+                "'{}' is not full, last, cursor, focused-window or a region like 0 0 50% 50%",
+                s
+            )),
+        }
+    }
+}
+
+/// Options set with `set <name> <value>` lines in the config file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Settings {
+    pub edge_policy: EdgePolicy,
+    /// Zero when region changes shouldn't be animated
+    pub animation_duration: Duration,
+    pub animation_easing: Easing,
+    pub pointer_backend: PointerBackendChoice,
+    pub surface: SurfaceChoice,
+    /// Of the active region and its outline
+    pub fill_color: Color,
+    pub border_color: Color,
+    pub initial_region: InitialRegion,
+    /// For `cursorzoom` without arguments
+    pub cursorzoom_size: (Length, Length),
+    /// Whether digits typed before a key make it run that many times
+    pub count_keys: bool,
+    /// How many motion events drag-end moves the pointer in
+    pub drag_steps: u32,
+    /// For the bindings after it in the config
+    pub button_numbering: ButtonNumbering,
+    /// Between the clicks of a doubleclick
+    pub doubleclick_interval: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            edge_policy: EdgePolicy::Clamp,
+            animation_duration: Duration::ZERO,
+            animation_easing: Easing::EaseOut,
+            pointer_backend: PointerBackendChoice::Auto,
+            surface: SurfaceChoice::Auto,
+            fill_color: Color {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+                a: 0.2,
+            },
+            border_color: Color {
+                r: 0.0,
+                g: 0.0,
+                b: 0.0,
+                a: 1.0,
+            },
+            initial_region: InitialRegion::Full,
+            cursorzoom_size: (Length::Pixels(100.0), Length::Pixels(100.0)),
+            count_keys: false,
+            drag_steps: 10,
+            button_numbering: ButtonNumbering::Evdev,
+            doubleclick_interval: Duration::ZERO,
+        }
+    }
+}
+
+impl Settings {
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "edge-policy" => self.edge_policy = EdgePolicy::parse(value)?,
+            "button-numbering" => self.button_numbering = ButtonNumbering::parse(value)?,
+            "animation-duration" => {
+                self.animation_duration = match value {
+                    "off" => Duration::ZERO,
+                    _ => Duration::from_millis(value.parse().map_err(|_| {
+                        format!("'{}' is not a number of milliseconds or off", value)
+                    })?),
+                }
+            }
+            "doubleclick-interval" => {
+                self.doubleclick_interval = Duration::from_millis(
+                    value
+                        .parse()
+                        .map_err(|_| format!("'{}' is not a number of milliseconds", value))?,
+                )
+            }
+            "animation-easing" => self.animation_easing = Easing::parse(value)?,
+            "pointer-backend" => self.pointer_backend = PointerBackendChoice::parse(value)?,
+            "surface" => self.surface = SurfaceChoice::parse(value)?,
+            "fill-color" => self.fill_color = Color::parse(value)?,
+            "border-color" => self.border_color = Color::parse(value)?,
+            "initial-region" => self.initial_region = InitialRegion::parse(value)?,
+            "cursorzoom-size" => {
+                self.cursorzoom_size = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                    [width, height] => {
+                        (Length::parse_pixels(width)?, Length::parse_pixels(height)?)
+                    }
+                    _ => return Err(format!("'{}' is not a width and a height", value)),
+                }
+            }
+            "drag-steps" => {
+                self.drag_steps = match value.parse() {
+                    Ok(steps) if steps > 0 => steps,
+                    _ => return Err(format!("'{}' is not a positive number of steps", value)),
+                }
+            }
+            "count-keys" => {
+                self.count_keys = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("'{}' is not on or off", value)),
+                }
+            }
+            _ => return Err(format!("Unknown setting '{}'", name)),
+        }
+        Ok(())
+    }
+    /// These settings with those of the `sections` that match the output
+    /// called `name` (eg. DP-1) applied on top.
+    pub fn for_output(&self, sections: &[OutputSection], name: &str, description: &str) -> Self {
+        let mut settings = self.clone();
+        for section in sections {
+            if section.matches(name, description) {
+                for (setting, value) in &section.settings {
+                    settings
+                        .set(setting, value)
+                        .expect("output settings to be checked while parsing");
+                }
+            }
+        }
+        settings
+    }
+}
+
+// Settings that only make sense for the whole app, not per output
+const GLOBAL_SETTINGS: [&str; 3] = ["pointer-backend", "surface", "button-numbering"];
+
+/// Settings from an `[output NAME]` section, for the output with that name
+/// or whose description contains it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutputSection {
+    pub output: String,
+    /// As name and value of the `set` lines
+    pub settings: Vec<(String, String)>,
+}
+
+impl OutputSection {
+    /// Whether the section is for the output called `name`, or one whose
+    /// description contains the section's text as whole words, so that DP-1
+    /// doesn't match DP-10
+    pub fn matches(&self, name: &str, description: &str) -> bool {
+        let is_word = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+        name == self.output
+            || description
+                .match_indices(self.output.as_str())
+                .any(|(start, text)| {
+                    !description[..start].ends_with(is_word)
+                        && !description[start + text.len()..].starts_with(is_word)
+                })
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Config {
     pub mappings: HashMap<(ModMask, Keysym), Vec<KeynavAction>>,
+    pub settings: Settings,
+    pub outputs: Vec<OutputSection>,
 }
 impl Config {
     pub fn from_raw_config(raw_config: &RawConfig, keymap: &Keymap) -> Result<Self, String> {
@@ -77,7 +821,7 @@ impl Config {
             for raw_key in raw_keys {
                 let maybe_mod_index = keymap.mod_get_index(raw_key);
                 if maybe_mod_index == MOD_INVALID {
-                    let maybe_keysym = keysym_from_name(&raw_key, KEYSYM_NO_FLAGS);
+                    let maybe_keysym = keysym_from_name(raw_key, KEYSYM_NO_FLAGS);
                     if maybe_keysym == KEY_NoSymbol {
                         return Err(format!(
                             "String '{}' is not recognized as mod or normal key",
@@ -105,12 +849,90 @@ impl Config {
                 }
             }
         }
-        Ok(Config { mappings })
+        Ok(Config {
+            mappings,
+            settings: raw_config.settings.clone(),
+            outputs: raw_config.outputs.clone(),
+        })
     }
 }
+/// Which windows a [Profile] applies to.
+#[derive(Debug, PartialEq, Clone)]
+pub enum WindowMatch {
+    /// Exactly this app_id (or class, for Xwayland windows)
+    AppId(String),
+    /// Titles containing this
+    Title(String),
+}
+
+impl WindowMatch {
+    /// Parses the inside of a section header, eg. `app firefox`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.split_once(char::is_whitespace) {
+            Some(("app", app_id)) if !app_id.trim().is_empty() => {
+                Ok(WindowMatch::AppId(app_id.trim().into()))
+            }
+            Some(("title", title)) if !title.trim().is_empty() => {
+                Ok(WindowMatch::Title(title.trim().into()))
+            }
+            _ => Err(format!(
+                "'[{}]' is not a section, expected [app APP_ID] or [title TEXT]",
+                s
+            )),
+        }
+    }
+    pub fn matches(&self, app_id: &str, title: &str) -> bool {
+        match self {
+            WindowMatch::AppId(expected) => app_id == expected,
+            WindowMatch::Title(part) => title.contains(part.as_str()),
+        }
+    }
+}
+
+impl std::fmt::Display for WindowMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WindowMatch::AppId(app_id) => write!(f, "app {}", app_id),
+            WindowMatch::Title(title) => write!(f, "title {}", title),
+        }
+    }
+}
+
+/// Bindings from an `[app ...]` or `[title ...]` section, used on top of the
+/// others while a matching window has focus.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Profile {
+    pub window: WindowMatch,
+    pub mappings: Vec<(Vec<String>, Vec<KeynavAction>)>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct RawConfig {
     pub mappings: Vec<(Vec<String>, Vec<KeynavAction>)>,
+    pub settings: Settings,
+    pub profiles: Vec<Profile>,
+    pub outputs: Vec<OutputSection>,
+}
+
+impl RawConfig {
+    /// The first profile for a window with `app_id` and `title`
+    pub fn profile_for(&self, app_id: &str, title: &str) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.window.matches(app_id, title))
+    }
+    /// The mappings with those of `profile` after them, so that they win
+    /// where both bind the same keys
+    pub fn layered_mappings(
+        &self,
+        profile: Option<&Profile>,
+    ) -> Vec<(Vec<String>, Vec<KeynavAction>)> {
+        self.mappings
+            .iter()
+            .chain(profile.into_iter().flat_map(|profile| &profile.mappings))
+            .cloned()
+            .collect()
+    }
 }
 
 pub fn default_config() -> RawConfig {
@@ -139,10 +961,7 @@ pub fn default_config() -> RawConfig {
             ),
             (
                 vec!["semicolon".into()],
-                vec![KeynavAction::CursorZoom {
-                    width: 100,
-                    height: 100,
-                }],
+                vec![KeynavAction::CursorZoom(None)],
             ),
             (
                 vec!["Return".into()],
@@ -154,6 +973,9 @@ pub fn default_config() -> RawConfig {
             ),
             (vec!["Escape".into()], vec![KeynavAction::End]),
         ],
+        settings: Settings::default(),
+        profiles: Vec::new(),
+        outputs: Vec::new(),
     }
 }
 
@@ -163,64 +985,165 @@ pub fn parse_key(raw: &str) -> Result<Keysym, String> {
         key => Ok(key),
     }
 }
+// The options of pick-color, in any order: a format, average and copy
+fn parse_pick_color(options: &[&str]) -> Result<KeynavAction, String> {
+    let mut format = None;
+    let mut average = false;
+    let mut copy = false;
+    for option in options {
+        match *option {
+            "average" if !average => average = true,
+            "copy" if !copy => copy = true,
+            option if format.is_none() => format = Some(ColorFormat::parse(option)?),
+            option => return Err(format!("Unexpected pick-color option '{}'", option)),
+        }
+    }
+    Ok(KeynavAction::PickColor {
+        format: format.unwrap_or(ColorFormat::Hex),
+        average,
+        copy,
+    })
+}
+
+// Everything after the first word of `action` as written, for arguments that
+// may contain runs of whitespace
+fn argument(action: &str) -> &str {
+    let action = action.trim_start();
+    let start = action.find(char::is_whitespace).unwrap_or(action.len());
+    action[start..].trim()
+}
+
 pub fn parse_actions(raw: &str) -> Result<Vec<KeynavAction>, String> {
-    raw.split(",")
+    parse_actions_with(raw, ButtonNumbering::Evdev)
+}
+
+/// Like [parse_actions], reading button numbers in `numbering`
+pub fn parse_actions_with(
+    raw: &str,
+    numbering: ButtonNumbering,
+) -> Result<Vec<KeynavAction>, String> {
+    let button = |v: &str| MouseButton::parse(v, numbering);
+    raw.split(',')
         .map(|x| {
-            let scrutinee: Vec<&str> = x.trim().split_whitespace().collect();
+            let scrutinee: Vec<&str> = x.split_whitespace().collect();
             match scrutinee[..] {
                 ["cut-right"] => Ok(KeynavAction::CutRight(None)),
-                ["cut-right", v] if v.parse::<f64>().is_ok() => {
-                    Ok(KeynavAction::CutRight(Some(v.parse::<f64>().unwrap())))
+                ["cut-right", v] if Length::parse(v).is_ok() => {
+                    Ok(KeynavAction::CutRight(Some(Length::parse(v).unwrap())))
                 }
                 ["cut-left"] => Ok(KeynavAction::CutLeft(None)),
-                ["cut-left", v] if v.parse::<f64>().is_ok() => {
-                    Ok(KeynavAction::CutLeft(Some(v.parse::<f64>().unwrap())))
+                ["cut-left", v] if Length::parse(v).is_ok() => {
+                    Ok(KeynavAction::CutLeft(Some(Length::parse(v).unwrap())))
                 }
                 ["cut-up"] => Ok(KeynavAction::CutUp(None)),
-                ["cut-up", v] if v.parse::<f64>().is_ok() => {
-                    Ok(KeynavAction::CutUp(Some(v.parse::<f64>().unwrap())))
+                ["cut-up", v] if Length::parse(v).is_ok() => {
+                    Ok(KeynavAction::CutUp(Some(Length::parse(v).unwrap())))
                 }
                 ["cut-down"] => Ok(KeynavAction::CutDown(None)),
-                ["cut-down", v] if v.parse::<f64>().is_ok() => {
-                    Ok(KeynavAction::CutDown(Some(v.parse::<f64>().unwrap())))
+                ["cut-down", v] if Length::parse(v).is_ok() => {
+                    Ok(KeynavAction::CutDown(Some(Length::parse(v).unwrap())))
                 }
                 ["move-right"] => Ok(KeynavAction::MoveRight(None)),
-                ["move-right", v] if v.parse::<f64>().is_ok() => {
-                    Ok(KeynavAction::MoveRight(Some(v.parse::<f64>().unwrap())))
+                ["move-right", v] if Length::parse(v).is_ok() => {
+                    Ok(KeynavAction::MoveRight(Some(Length::parse(v).unwrap())))
                 }
                 ["move-left"] => Ok(KeynavAction::MoveLeft(None)),
-                ["move-left", v] if v.parse::<f64>().is_ok() => {
-                    Ok(KeynavAction::MoveLeft(Some(v.parse::<f64>().unwrap())))
+                ["move-left", v] if Length::parse(v).is_ok() => {
+                    Ok(KeynavAction::MoveLeft(Some(Length::parse(v).unwrap())))
                 }
                 ["move-up"] => Ok(KeynavAction::MoveUp(None)),
-                ["move-up", v] if v.parse::<f64>().is_ok() => {
-                    Ok(KeynavAction::MoveUp(Some(v.parse::<f64>().unwrap())))
+                ["move-up", v] if Length::parse(v).is_ok() => {
+                    Ok(KeynavAction::MoveUp(Some(Length::parse(v).unwrap())))
                 }
                 ["move-down"] => Ok(KeynavAction::MoveDown(None)),
-                ["move-down", v] if v.parse::<f64>().is_ok() => {
-                    Ok(KeynavAction::MoveDown(Some(v.parse::<f64>().unwrap())))
+                ["move-down", v] if Length::parse(v).is_ok() => {
+                    Ok(KeynavAction::MoveDown(Some(Length::parse(v).unwrap())))
                 }
                 ["cursorzoom", width, height]
-                    if width.parse::<u32>().is_ok() && height.parse::<u32>().is_ok() =>
+                    if Length::parse_pixels(width).is_ok()
+                        && Length::parse_pixels(height).is_ok() =>
+                {
+                    Ok(KeynavAction::CursorZoom(Some((
+                        Length::parse_pixels(width).unwrap(),
+                        Length::parse_pixels(height).unwrap(),
+                    ))))
+                }
+                ["cursorzoom"] => Ok(KeynavAction::CursorZoom(None)),
+
+                ["region", x, y, width, height]
+                    if [x, y, width, height]
+                        .iter()
+                        .all(|v| Length::parse(v).is_ok()) =>
                 {
-                    Ok(KeynavAction::CursorZoom {
-                        width: width.parse::<u32>().unwrap(),
-                        height: height.parse::<u32>().unwrap(),
+                    Ok(KeynavAction::Region {
+                        x: Length::parse(x).unwrap(),
+                        y: Length::parse(y).unwrap(),
+                        width: Length::parse(width).unwrap(),
+                        height: Length::parse(height).unwrap(),
+                    })
+                }
+                ["move-to", x, y] if Length::parse(x).is_ok() && Length::parse(y).is_ok() => {
+                    Ok(KeynavAction::MoveTo {
+                        x: Length::parse(x).unwrap(),
+                        y: Length::parse(y).unwrap(),
                     })
                 }
 
                 ["warp"] => Ok(KeynavAction::Warp),
-                ["click"] => Ok(KeynavAction::Click(None)),
-                ["click", v] if MouseButton::parse(v).is_ok() => {
-                    Ok(KeynavAction::Click(Some(MouseButton::parse(v).unwrap())))
+                ["warp-to", v] if Anchor::parse(v).is_ok() => {
+                    Ok(KeynavAction::WarpTo(Anchor::parse(v).unwrap()))
                 }
+                ["click"] => Ok(KeynavAction::Click(None)),
+                // Buttons tell what is wrong with them, eg. which numbers
+                // there are
+                ["click", v] => button(v).map(|b| KeynavAction::Click(Some(b))),
                 ["doubleclick"] => Ok(KeynavAction::DoubleClick(None)),
-                ["doubleclick", v] if MouseButton::parse(v).is_ok() => Ok(
-                    KeynavAction::DoubleClick(Some(MouseButton::parse(v).unwrap())),
-                ),
-                ["drag", v] if MouseButton::parse(v).is_ok() => {
-                    Ok(KeynavAction::DragButton(MouseButton::parse(v).unwrap()))
+                ["doubleclick", v] => button(v).map(|b| KeynavAction::DoubleClick(Some(b))),
+                ["drag", v] => button(v).map(KeynavAction::DragButton),
+                ["drag-start"] => Ok(KeynavAction::DragStart(None)),
+                ["drag-start", v] => button(v).map(|b| KeynavAction::DragStart(Some(b))),
+                ["drag-end"] => Ok(KeynavAction::DragEnd),
+                ["hold", v, ms] if ms.parse::<u64>().is_ok() => button(v)
+                    .map(|b| KeynavAction::Hold(b, Duration::from_millis(ms.parse().unwrap()))),
+                ["sleep", ms] if ms.parse::<u64>().is_ok() => Ok(KeynavAction::Sleep(
+                    Duration::from_millis(ms.parse().unwrap()),
+                )),
+
+                ["magnify"] => Ok(KeynavAction::Magnify),
+                ["window-select"] => Ok(KeynavAction::WindowSelect),
+                ["repeat"] => Ok(KeynavAction::Repeat),
+                ["mark", name] => Ok(KeynavAction::Mark(name.into())),
+                ["goto-mark", name] => Ok(KeynavAction::GotoMark {
+                    name: name.into(),
+                    warp: false,
+                }),
+                ["goto-mark", name, "warp"] => Ok(KeynavAction::GotoMark {
+                    name: name.into(),
+                    warp: true,
+                }),
+                ["pick-color", ref options @ ..] if parse_pick_color(options).is_ok() => {
+                    parse_pick_color(options)
+                }
+                ["print", ref format @ ..]
+                    if !format.is_empty() && TextFormat::parse(&format.join(" ")).is_ok() =>
+                {
+                    Ok(KeynavAction::Print(
+                        TextFormat::parse(&format.join(" ")).unwrap(),
+                    ))
                 }
+                ["copy", ref format @ ..]
+                    if !format.is_empty() && TextFormat::parse(&format.join(" ")).is_ok() =>
+                {
+                    Ok(KeynavAction::Copy(
+                        TextFormat::parse(&format.join(" ")).unwrap(),
+                    ))
+                }
+                ["screenshot", _, ..] => Ok(KeynavAction::Screenshot(argument(x).into())),
+
+                ["key", combo] if KeyCombo::parse(combo).is_ok() => {
+                    Ok(KeynavAction::Key(KeyCombo::parse(combo).unwrap()))
+                }
+                ["type", _, ..] => Ok(KeynavAction::Type(argument(x).into())),
 
                 ["end"] => Ok(KeynavAction::End),
                 _ => Err(format!(
@@ -235,36 +1158,128 @@ pub fn parse_actions(raw: &str) -> Result<Vec<KeynavAction>, String> {
 /*
 The config file format is defined as a collection of lines separated by
 newlines, where each line is either blank (containing any number of whitespace
-characters other than '\n'), or a definition. A definition is a keyname followed
-by whitespace followed by a nonempty comma separated list of actions.
+characters other than '\n'), a setting or a definition. A setting is the word
+"set" followed by whitespace, a setting name, whitespace and a value. A
+definition is a keyname followed by whitespace followed by a nonempty comma
+separated list of actions. A line like "[app firefox]" or "[title Vim]" starts
+a profile, and the definitions after it belong to the profile instead. After a
+line like "[output DP-1]" settings only apply to that output, and there can't
+be definitions.
 */
 fn parse_config(contents: String) -> Result<RawConfig, String> {
+    // Which kind of section the lines are in
+    enum Section {
+        Base,
+        Profile,
+        Output,
+    }
     let mut mappings: Vec<(Vec<String>, Vec<KeynavAction>)> = Vec::new();
-    let mut line_num = 1;
-    for line in contents.split('\n') {
+    let mut settings = Settings::default();
+    let mut profiles: Vec<Profile> = Vec::new();
+    let mut outputs: Vec<OutputSection> = Vec::new();
+    let mut section = Section::Base;
+    for (line_num, line) in (1..).zip(contents.split('\n')) {
         match line.trim() {
             "" => {}
+            line if line.starts_with('[') && line.ends_with(']') => {
+                let header = line[1..line.len() - 1].trim();
+                match header.split_once(char::is_whitespace) {
+                    Some(("output", output)) if !output.trim().is_empty() => {
+                        outputs.push(OutputSection {
+                            output: output.trim().into(),
+                            settings: Vec::new(),
+                        });
+                        section = Section::Output;
+                    }
+                    _ => {
+                        let window = WindowMatch::parse(header)
+                            .map_err(|err| format!("Error on line {}: {}", line_num, err))?;
+                        profiles.push(Profile {
+                            window,
+                            mappings: Vec::new(),
+                        });
+                        section = Section::Profile;
+                    }
+                }
+            }
             line => {
-                if line.chars().nth(0).unwrap_or('_') != '#' {
-                    match line.find(char::is_whitespace) {
-                        None => {
+                if !line.starts_with('#') {
+                    match (line.find(char::is_whitespace), &section) {
+                        (None, _) => {
                             return Err(format!("Error on line {}: Line is not empty, but does not have two whitespace separated sections", line_num));
                         }
-                        Some(i) => {
+                        (Some(i), Section::Profile) if &line[..i] == "set" => {
+                            return Err(format!(
+                                "Error on line {}: Settings can't be changed in a profile",
+                                line_num
+                            ));
+                        }
+                        (Some(i), _) if &line[..i] == "set" => {
+                            match line[i..].trim().split_once(char::is_whitespace) {
+                                Some((name, value)) => {
+                                    let error =
+                                        |err| format!("Error on line {}: {}", line_num, err);
+                                    match outputs.last_mut() {
+                                        Some(output) if matches!(section, Section::Output) => {
+                                            if GLOBAL_SETTINGS.contains(&name) {
+                                                return Err(error(format!(
+                                                    "'{}' can't be set per output",
+                                                    name
+                                                )));
+                                            }
+                                            // Checked now so that it can't
+                                            // fail once we know the output
+                                            Settings::default()
+                                                .set(name, value.trim())
+                                                .map_err(error)?;
+                                            output
+                                                .settings
+                                                .push((name.into(), value.trim().into()));
+                                        }
+                                        _ => settings.set(name, value.trim()).map_err(error)?,
+                                    }
+                                }
+                                None => {
+                                    return Err(format!(
+                                        "Error on line {}: Setting has no value",
+                                        line_num
+                                    ))
+                                }
+                            }
+                        }
+                        (Some(_), Section::Output) => {
+                            return Err(format!(
+                                "Error on line {}: Bindings can't be changed per output",
+                                line_num
+                            ));
+                        }
+                        (Some(i), _) => {
                             let (keys, actions) = line.split_at(i);
-                            let keys = keys.split("+").map(String::from).collect();
-                            let actions = parse_actions(actions.trim())
-                                .map_err(|err| format!("Error on line {}: {}", line_num, err))?;
-                            mappings.push((keys, actions));
+                            let keys = keys.split('+').map(String::from).collect();
+                            let actions =
+                                parse_actions_with(actions.trim(), settings.button_numbering)
+                                    .map_err(|err| {
+                                        format!("Error on line {}: {}", line_num, err)
+                                    })?;
+                            match profiles.last_mut() {
+                                Some(profile) if matches!(section, Section::Profile) => {
+                                    profile.mappings.push((keys, actions))
+                                }
+                                _ => mappings.push((keys, actions)),
+                            }
                         }
                     }
                 }
             }
         }
-        line_num = line_num + 1;
     }
 
-    Ok(RawConfig { mappings })
+    Ok(RawConfig {
+        mappings,
+        settings,
+        profiles,
+        outputs,
+    })
 }
 pub fn parse_config_file(config: &mut File) -> Result<RawConfig, String> {
     let mut contents = String::new();
@@ -320,10 +1335,10 @@ Escape end";
                 ),
                 (
                     vec!["semicolon".into()],
-                    vec![KeynavAction::CursorZoom {
-                        width: 100,
-                        height: 100,
-                    }],
+                    vec![KeynavAction::CursorZoom(Some((
+                        Length::Pixels(100.0),
+                        Length::Pixels(100.0),
+                    )))],
                 ),
                 (
                     vec!["Return".into()],
@@ -335,7 +1350,488 @@ Escape end";
                 ),
                 (vec!["Escape".into()], vec![KeynavAction::End]),
             ],
+            settings: Settings::default(),
+            profiles: Vec::new(),
+            outputs: Vec::new(),
         });
         assert_eq!(expected, parse_config(config.to_string()));
     }
+
+    #[test]
+    fn parse_units() {
+        let expected = Ok(vec![
+            KeynavAction::CutLeft(Some(Length::Pixels(200.0))),
+            KeynavAction::MoveUp(Some(Length::Fraction(0.1))),
+            KeynavAction::MoveDown(Some(Length::Fraction(0.25))),
+            KeynavAction::CursorZoom(Some((Length::Millimeters(20.0), Length::Pixels(30.0)))),
+        ]);
+        assert_eq!(
+            expected,
+            parse_actions("cut-left 200px, move-up 10%, move-down 0.25, cursorzoom 20mm 30")
+        );
+        assert!(parse_actions("cut-left 2furlongs").is_err());
+        assert!(parse_actions("cut-left -10px").is_err());
+    }
+
+    #[test]
+    fn resolve_units() {
+        assert_eq!(0.25, Length::Fraction(0.25).to_fraction(800.0, 4.0));
+        assert_eq!(0.25, Length::Pixels(200.0).to_fraction(800.0, 4.0));
+        assert_eq!(0.25, Length::Millimeters(50.0).to_fraction(800.0, 4.0));
+    }
+
+    #[test]
+    fn parse_absolute_positioning() {
+        let expected = Ok(vec![
+            KeynavAction::Region {
+                x: Length::Fraction(0.5),
+                y: Length::Pixels(10.0),
+                width: Length::Fraction(0.25),
+                height: Length::Millimeters(30.0),
+            },
+            KeynavAction::MoveTo {
+                x: Length::Pixels(100.0),
+                y: Length::Fraction(0.5),
+            },
+            KeynavAction::WarpTo(Anchor::BottomRight),
+        ]);
+        assert_eq!(
+            expected,
+            parse_actions("region 0.5 10px 25% 30mm, move-to 100px 0.5, warp-to bottom-right")
+        );
+        assert!(parse_actions("region 0.5 0.5 0.5").is_err());
+        assert!(parse_actions("warp-to middle").is_err());
+    }
+
+    #[test]
+    fn pick_color_options() {
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::PickColor {
+                    format: ColorFormat::Hex,
+                    average: false,
+                    copy: false,
+                },
+                KeynavAction::PickColor {
+                    format: ColorFormat::Hsl,
+                    average: true,
+                    copy: true,
+                },
+            ]),
+            parse_actions("pick-color, pick-color copy average hsl")
+        );
+        assert!(parse_actions("pick-color cmyk").is_err());
+        assert!(parse_actions("pick-color hex rgb").is_err());
+        assert!(parse_actions("pick-color copy copy").is_err());
+    }
+
+    #[test]
+    fn text_formats() {
+        let value = |c| match c {
+            'x' => "1".to_string(),
+            'y' => "2".to_string(),
+            'w' => "3".to_string(),
+            'h' => "4".to_string(),
+            c => c.to_string(),
+        };
+        assert_eq!(
+            "1,2 3x4",
+            TextFormat::parse("geometry").unwrap().expand(value)
+        );
+        assert_eq!("X,Y", TextFormat::parse("center").unwrap().expand(value));
+        assert_eq!(
+            "at 1 2 on o (100%)",
+            TextFormat::parse("at %x %y on %o (100%%)")
+                .unwrap()
+                .expand(value)
+        );
+        assert!(TextFormat::parse("%q").is_err());
+        assert!(TextFormat::parse("50%").is_err());
+
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::Print(TextFormat::parse("%x %y").unwrap()),
+                KeynavAction::Copy(TextFormat::parse("center").unwrap()),
+            ]),
+            parse_actions("print %x %y, copy center")
+        );
+        assert!(parse_actions("copy %z").is_err());
+        assert!(parse_actions("print").is_err());
+    }
+
+    #[test]
+    fn parse_marks() {
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::Mark("a".into()),
+                KeynavAction::GotoMark {
+                    name: "a".into(),
+                    warp: false
+                },
+                KeynavAction::GotoMark {
+                    name: "b".into(),
+                    warp: true
+                },
+            ]),
+            parse_actions("mark a, goto-mark a, goto-mark b warp")
+        );
+        assert!(parse_actions("mark").is_err());
+        assert!(parse_actions("goto-mark a b").is_err());
+    }
+
+    #[test]
+    fn parse_buttons() {
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::Click(Some(MouseButton::Right)),
+                KeynavAction::Click(Some(MouseButton::Back)),
+                KeynavAction::DragButton(MouseButton::Middle),
+            ]),
+            parse_actions("click 2, click 7, drag middle")
+        );
+        assert_eq!(0x116, MouseButton::Back.to_code());
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::Click(Some(MouseButton::Side)),
+                KeynavAction::Click(Some(MouseButton::Extra)),
+            ]),
+            parse_actions("click back, click forward")
+        );
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::Click(Some(MouseButton::Back)),
+                KeynavAction::Click(Some(MouseButton::Forward)),
+            ]),
+            parse_actions("click btn-back, click btn-forward")
+        );
+        assert_eq!(
+            Err(
+                "There is no button 9 in evdev numbering, where 1 is left, 2 right, \
+                 3 middle, 4 side, 5 extra, 6 btn-forward, 7 btn-back and 8 task"
+                    .into()
+            ),
+            parse_actions("click 9")
+        );
+        assert!(parse_actions("click up").is_err());
+        assert!(parse_actions("hold up 100").is_err());
+
+        let config = parse_config(
+            "\
+a click 2
+set button-numbering x11
+b click 2
+c click 3, click 8, click 9"
+                .into(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                (
+                    vec!["a".into()],
+                    vec![KeynavAction::Click(Some(MouseButton::Right))]
+                ),
+                (
+                    vec!["b".into()],
+                    vec![KeynavAction::Click(Some(MouseButton::Middle))]
+                ),
+                (
+                    vec!["c".into()],
+                    vec![
+                        KeynavAction::Click(Some(MouseButton::Right)),
+                        KeynavAction::Click(Some(MouseButton::Side)),
+                        KeynavAction::Click(Some(MouseButton::Extra)),
+                    ]
+                ),
+            ],
+            config.mappings
+        );
+        assert_eq!(
+            Err(
+                "There is no button 4 in x11 numbering, where 1 is left, 2 middle, \
+                 3 right, 8 side and 9 extra, and 4 to 7 are the scroll wheel, which \
+                 can't be clicked"
+                    .into()
+            ),
+            parse_actions_with("click 4", ButtonNumbering::X11)
+        );
+        assert!(parse_config("set button-numbering x11\na click 4".into()).is_err());
+        assert!(parse_config("[output DP-1]\nset button-numbering x11".into()).is_err());
+    }
+
+    #[test]
+    fn parse_timed_actions() {
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::Hold(MouseButton::Left, Duration::from_millis(600)),
+                KeynavAction::Sleep(Duration::from_millis(50)),
+                KeynavAction::Click(None),
+            ]),
+            parse_actions("hold 1 600, sleep 50, click")
+        );
+        assert!(parse_actions("hold 1").is_err());
+        assert!(parse_actions("sleep -1").is_err());
+        assert!(parse_actions("sleep 1s").is_err());
+
+        let settings = parse_config("set doubleclick-interval 120".into())
+            .unwrap()
+            .settings;
+        assert_eq!(Duration::from_millis(120), settings.doubleclick_interval);
+        assert!(parse_config("set doubleclick-interval off".into()).is_err());
+    }
+
+    #[test]
+    fn parse_keys() {
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::Key(KeyCombo {
+                    modifiers: vec![Modifier::Control, Modifier::Shift],
+                    keysym: KEY_t,
+                }),
+                KeynavAction::Key(KeyCombo {
+                    modifiers: vec![],
+                    keysym: KEY_Return,
+                }),
+                KeynavAction::Type("hello  world".into()),
+            ]),
+            parse_actions("key ctrl+shift+t, key return, type hello  world")
+        );
+        assert!(parse_actions("key hyper+a").is_err());
+        assert!(parse_actions("key ctrl+").is_err());
+        assert!(parse_actions("key nokey").is_err());
+        assert!(parse_actions("type").is_err());
+    }
+
+    #[test]
+    fn parse_screenshot() {
+        assert_eq!(
+            Ok(vec![
+                KeynavAction::Screenshot("~/Pictures/shot %Y%m%d.png".into()),
+                KeynavAction::End
+            ]),
+            parse_actions("screenshot ~/Pictures/shot %Y%m%d.png, end")
+        );
+        assert_eq!(
+            Ok(vec![KeynavAction::Screenshot("shot  %H:%M.png".into())]),
+            parse_actions(" screenshot  shot  %H:%M.png ")
+        );
+        assert!(parse_actions("screenshot").is_err());
+    }
+
+    #[test]
+    fn color_formats() {
+        let colour = (0x64, 0x32, 0x80);
+        assert_eq!("#643280", ColorFormat::Hex.format(colour));
+        assert_eq!("rgb(100, 50, 128)", ColorFormat::Rgb.format(colour));
+        assert_eq!("hsl(278, 44%, 35%)", ColorFormat::Hsl.format(colour));
+        assert_eq!("hsl(0, 0%, 100%)", ColorFormat::Hsl.format((255, 255, 255)));
+        assert_eq!("hsl(0, 100%, 50%)", ColorFormat::Hsl.format((255, 0, 0)));
+        assert_eq!("hsl(240, 100%, 50%)", ColorFormat::Hsl.format((0, 0, 255)));
+    }
+
+    #[test]
+    fn easing_curves() {
+        for easing in [Easing::Linear, Easing::EaseOut, Easing::EaseInOut] {
+            assert_eq!(0.0, easing.apply(0.0));
+            assert_eq!(1.0, easing.apply(1.0));
+            assert_eq!(1.0, easing.apply(1.5));
+            let mut last = 0.0;
+            for i in 1..=10 {
+                let progress = easing.apply(i as f64 / 10.0);
+                assert!(progress > last, "{:?} is not increasing", easing);
+                last = progress;
+            }
+        }
+        assert!(Easing::EaseOut.apply(0.5) > 0.5);
+        assert_eq!(0.5, Easing::EaseInOut.apply(0.5));
+    }
+
+    #[test]
+    fn parse_settings() {
+        let config = "\
+set edge-policy wrap
+h cut-left";
+        let expected = Ok(RawConfig {
+            mappings: vec![(vec!["h".into()], vec![KeynavAction::CutLeft(None)])],
+            settings: Settings {
+                edge_policy: EdgePolicy::Wrap,
+                ..Settings::default()
+            },
+            profiles: Vec::new(),
+            outputs: Vec::new(),
+        });
+        assert_eq!(expected, parse_config(config.to_string()));
+        assert!(parse_config("set edge-policy bounce".to_string()).is_err());
+        assert!(parse_config("set edge-policy".to_string()).is_err());
+        assert!(parse_config("set frobnicate yes".to_string()).is_err());
+
+        let config = "\
+set animation-duration 150
+set animation-easing linear";
+        let settings = parse_config(config.to_string()).unwrap().settings;
+        assert_eq!(Duration::from_millis(150), settings.animation_duration);
+        assert_eq!(Easing::Linear, settings.animation_easing);
+        let settings = parse_config("set animation-duration off".to_string())
+            .unwrap()
+            .settings;
+        assert_eq!(Duration::ZERO, settings.animation_duration);
+        assert!(parse_config("set animation-duration fast".to_string()).is_err());
+        assert!(parse_config("set animation-easing bounce".to_string()).is_err());
+
+        let settings = parse_config("set pointer-backend kde-fake-input".to_string())
+            .unwrap()
+            .settings;
+        assert_eq!(PointerBackendChoice::KdeFakeInput, settings.pointer_backend);
+        assert!(parse_config("set pointer-backend x11".to_string()).is_err());
+
+        let settings = parse_config("set surface xdg-shell".to_string())
+            .unwrap()
+            .settings;
+        assert_eq!(SurfaceChoice::XdgShell, settings.surface);
+        assert!(parse_config("set surface popup".to_string()).is_err());
+
+        assert!(
+            parse_config("set count-keys on".to_string())
+                .unwrap()
+                .settings
+                .count_keys
+        );
+        assert!(parse_config("set count-keys 1".to_string()).is_err());
+
+        let settings = parse_config("set drag-steps 3".to_string())
+            .unwrap()
+            .settings;
+        assert_eq!(3, settings.drag_steps);
+        assert!(parse_config("set drag-steps 0".to_string()).is_err());
+    }
+
+    #[test]
+    fn parse_profiles() {
+        let config = "\
+h cut-left
+[app firefox]
+f warp, click 1
+h move-left
+[title  - Vim ]
+j cut-down";
+        let config = parse_config(config.to_string()).unwrap();
+        assert_eq!(
+            vec![(vec!["h".into()], vec![KeynavAction::CutLeft(None)])],
+            config.mappings
+        );
+        assert_eq!(
+            vec![
+                WindowMatch::AppId("firefox".into()),
+                WindowMatch::Title("- Vim".into())
+            ],
+            config
+                .profiles
+                .iter()
+                .map(|profile| profile.window.clone())
+                .collect::<Vec<_>>()
+        );
+
+        let profile = config.profile_for("firefox", "Mozilla Firefox");
+        assert_eq!(Some(&config.profiles[0]), profile);
+        assert_eq!(
+            vec![
+                (vec!["h".into()], vec![KeynavAction::CutLeft(None)]),
+                (
+                    vec!["f".into()],
+                    vec![
+                        KeynavAction::Warp,
+                        KeynavAction::Click(Some(MouseButton::Left))
+                    ]
+                ),
+                (vec!["h".into()], vec![KeynavAction::MoveLeft(None)]),
+            ],
+            config.layered_mappings(profile)
+        );
+        assert_eq!(
+            Some(&config.profiles[1]),
+            config.profile_for("foot", "notes.txt - Vim")
+        );
+        assert_eq!(None, config.profile_for("Firefox", "notes.txt"));
+        assert_eq!(config.mappings, config.layered_mappings(None));
+
+        assert!(parse_config("[window foo]".to_string()).is_err());
+        assert!(parse_config("[app]".to_string()).is_err());
+        assert!(parse_config("[app foot]\nset edge-policy wrap".to_string()).is_err());
+    }
+
+    #[test]
+    fn parse_output_sections() {
+        let config = "\
+set fill-color #ff000033
+set cursorzoom-size 150 10mm
+[output DP-1]
+set border-color #00ff00
+set initial-region 0 0 50% 50%
+[output Dell]
+set cursorzoom-size 300 300";
+        let config = parse_config(config.to_string()).unwrap();
+        let red = Color {
+            r: 1.0,
+            g: 0.0,
+            b: 0.0,
+            a: 0.2,
+        };
+        assert_eq!(red, config.settings.fill_color);
+        assert_eq!(
+            (Length::Pixels(150.0), Length::Millimeters(10.0)),
+            config.settings.cursorzoom_size
+        );
+        assert_eq!(InitialRegion::Full, config.settings.initial_region);
+
+        let settings = config
+            .settings
+            .for_output(&config.outputs, "DP-1", "Dell Inc. DELL U2720Q");
+        assert_eq!(red, settings.fill_color);
+        assert_eq!(
+            Color {
+                r: 0.0,
+                g: 1.0,
+                b: 0.0,
+                a: 1.0
+            },
+            settings.border_color
+        );
+        assert_eq!(
+            InitialRegion::Fixed {
+                x: Length::Fraction(0.0),
+                y: Length::Fraction(0.0),
+                width: Length::Fraction(0.5),
+                height: Length::Fraction(0.5)
+            },
+            settings.initial_region
+        );
+        // Matched by description
+        assert_eq!(
+            (Length::Pixels(300.0), Length::Pixels(300.0)),
+            settings.cursorzoom_size
+        );
+        assert_eq!(
+            config.settings,
+            config
+                .settings
+                .for_output(&config.outputs, "eDP-1", "BOE 0x0BCA")
+        );
+        // Neither part of another name nor of a word in the description
+        assert_eq!(
+            config.settings,
+            config
+                .settings
+                .for_output(&config.outputs, "DP-10", "Delltron Q27 (DP-10)")
+        );
+
+        assert!(parse_config("[output DP-1]\nh cut-left".to_string()).is_err());
+        assert!(parse_config("[output DP-1]\nset surface xdg-shell".to_string()).is_err());
+        assert!(parse_config("[output DP-1]\nset fill-color red".to_string()).is_err());
+        assert!(parse_config("set initial-region 0 0".to_string()).is_err());
+        assert!(parse_config("set initial-region previous".to_string()).is_err());
+        let settings = parse_config("set initial-region focused-window".to_string())
+            .unwrap()
+            .settings;
+        assert_eq!(InitialRegion::FocusedWindow, settings.initial_region);
+        assert!(parse_config("set fill-color #12345".to_string()).is_err());
+    }
 }
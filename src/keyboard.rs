@@ -0,0 +1,224 @@
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+
+use wayland_client::{protocol::wl_keyboard, protocol::wl_seat, Main};
+use xkbcommon::xkb;
+
+use crate::config::KeyCombo;
+use crate::pointer::now_ms;
+use crate::protocols::virtual_keyboard::client::{
+    zwp_virtual_keyboard_manager_v1, zwp_virtual_keyboard_v1,
+};
+
+/// What the key and type actions send to the window under the overlay.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Key(KeyCombo),
+    Text(String),
+}
+
+impl Input {
+    // The keysyms to press one after the other, each with the modifier mask
+    // to hold down while pressing it
+    fn strokes(&self) -> Vec<(u32, xkb::Keysym)> {
+        match self {
+            Self::Key(combo) => {
+                let mask = combo.modifiers.iter().fold(0, |mask, m| mask | m.mask());
+                vec![(mask, combo.keysym)]
+            }
+            Self::Text(text) => text.chars().map(|c| (0, char_to_keysym(c))).collect(),
+        }
+    }
+}
+
+/// The keysym that types `c`
+fn char_to_keysym(c: char) -> xkb::Keysym {
+    match c {
+        '\n' => xkb::KEY_Return,
+        '\t' => xkb::KEY_Tab,
+        // Latin-1 keysyms are the same as its code points
+        ' '..='~' | '\u{a0}'..='\u{ff}' => c as u32,
+        _ => 0x0100_0000 | c as u32,
+    }
+}
+
+// The keys behind the modifiers of a [KeyCombo], which are never pressed but
+// tell clients which modifier is which, with the real modifier of each
+const MODIFIER_KEYS: [(&str, &str, &str); 4] = [
+    ("LFSH", "Shift_L", "Shift"),
+    ("LCTL", "Control_L", "Control"),
+    ("LALT", "Alt_L", "Mod1"),
+    ("LWIN", "Super_L", "Mod4"),
+];
+
+/// A keymap with a key for each of `keysyms`, at keycodes from 9 on (evdev
+/// codes from 1 on), followed by the [MODIFIER_KEYS]. It includes nothing, so
+/// that it works without xkeyboard-config installed.
+fn keymap(keysyms: &[xkb::Keysym]) -> String {
+    let mut keycodes = String::new();
+    let mut symbols = String::new();
+    for (i, keysym) in keysyms.iter().enumerate() {
+        keycodes += &format!("        <K{}> = {};\n", i + 1, i + 9);
+        symbols += &format!(
+            "        key <K{}> {{ [ {} ] }};\n",
+            i + 1,
+            xkb::keysym_get_name(*keysym)
+        );
+    }
+    for (i, (name, keysym, modifier)) in MODIFIER_KEYS.iter().enumerate() {
+        keycodes += &format!("        <{}> = {};\n", name, keysyms.len() + i + 9);
+        symbols += &format!("        key <{}> {{ [ {} ] }};\n", name, keysym);
+        symbols += &format!("        modifier_map {} {{ <{}> }};\n", modifier, name);
+    }
+    format!(
+        "xkb_keymap {{
+    xkb_keycodes \"keynav\" {{
+        minimum = 8;
+        maximum = {};
+{}    }};
+    xkb_types \"keynav\" {{
+        type \"ONE_LEVEL\" {{
+            modifiers = none;
+            level_name[Level1] = \"Any\";
+        }};
+    }};
+    xkb_compatibility \"keynav\" {{
+        virtual_modifiers Alt, Super;
+        interpret Alt_L {{
+            virtualModifier = Alt;
+        }};
+        interpret Super_L {{
+            virtualModifier = Super;
+        }};
+    }};
+    xkb_symbols \"keynav\" {{
+{}    }};
+}};
+",
+        (keysyms.len() + MODIFIER_KEYS.len() + 8).max(255),
+        keycodes,
+        symbols
+    )
+}
+
+/// zwp_virtual_keyboard_v1, which gets a keymap made for what it types each
+/// time.
+pub struct VirtualKeyboard {
+    manager: Main<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1>,
+    // Created on first use, as it shows up as a new keyboard to the
+    // compositor
+    keyboard: Option<Main<zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1>>,
+    // Kept open until the next keymap, in case the fd is only sent on flush
+    keymap_file: Option<File>,
+}
+
+impl VirtualKeyboard {
+    pub fn new(
+        manager: Main<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1>,
+    ) -> Self {
+        Self {
+            manager,
+            keyboard: None,
+            keymap_file: None,
+        }
+    }
+
+    /// Presses and releases the keys of `inputs` in order
+    pub fn send(&mut self, seat: &wl_seat::WlSeat, inputs: &[Input]) -> Result<(), String> {
+        let strokes: Vec<(u32, xkb::Keysym)> = inputs.iter().flat_map(Input::strokes).collect();
+        let mut keysyms: Vec<xkb::Keysym> = Vec::new();
+        for (_, keysym) in &strokes {
+            if !keysyms.contains(keysym) {
+                keysyms.push(*keysym);
+            }
+        }
+
+        let mut file = tempfile::tempfile().map_err(|err| err.to_string())?;
+        let mut contents = keymap(&keysyms).into_bytes();
+        // The size the compositor maps includes the terminating NUL
+        contents.push(0);
+        file.write_all(&contents).map_err(|err| err.to_string())?;
+
+        let manager = &self.manager;
+        let keyboard = self
+            .keyboard
+            .get_or_insert_with(|| manager.create_virtual_keyboard(seat));
+        keyboard.keymap(
+            wl_keyboard::KeymapFormat::XkbV1.to_raw(),
+            file.as_raw_fd(),
+            contents.len() as u32,
+        );
+        self.keymap_file = Some(file);
+
+        for (mask, keysym) in strokes {
+            // Evdev codes are 8 less than xkb keycodes
+            let key = keysyms.iter().position(|k| *k == keysym).unwrap() as u32 + 1;
+            if mask != 0 {
+                keyboard.modifiers(mask, 0, 0, 0);
+            }
+            keyboard.key(now_ms(), key, wl_keyboard::KeyState::Pressed.to_raw());
+            keyboard.key(now_ms(), key, wl_keyboard::KeyState::Released.to_raw());
+            if mask != 0 {
+                keyboard.modifiers(0, 0, 0, 0);
+            }
+        }
+        Ok(())
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[allow(unused_imports)]
+    use crate::config::Modifier;
+
+    #[test]
+    fn text_is_split_into_keysyms() {
+        assert_eq!(
+            vec![
+                (0, xkb::KEY_H),
+                (0, xkb::KEY_eacute),
+                (0, 0x0100_4e2d),
+                (0, xkb::KEY_Return)
+            ],
+            Input::Text("Hé中\n".into()).strokes()
+        );
+        let combo = KeyCombo {
+            modifiers: vec![Modifier::Control, Modifier::Shift],
+            keysym: xkb::KEY_t,
+        };
+        assert_eq!(vec![(0b101, xkb::KEY_t)], Input::Key(combo).strokes());
+    }
+
+    #[test]
+    fn keymap_compiles() {
+        let keysyms = [xkb::KEY_a, xkb::KEY_Return, 0x0100_4e2d];
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            keymap(&keysyms),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .expect("Generated keymap doesn't compile");
+        let state = xkb::State::new(&keymap);
+        for (keycode, keysym) in (9..).zip(keysyms) {
+            assert_eq!(keysym, state.key_get_one_sym(keycode));
+        }
+        assert_eq!("中", state.key_get_utf8(11));
+
+        // Alt and Super stand for the real modifiers that [Modifier::mask]
+        // gives them
+        for (virtual_modifier, modifier) in [("Alt", Modifier::Alt), ("Super", Modifier::Super)] {
+            let mut state = xkb::State::new(&keymap);
+            state.update_mask(1 << keymap.mod_get_index(virtual_modifier), 0, 0, 0, 0, 0);
+            assert_eq!(
+                modifier.mask(),
+                // Of the real modifiers, which come first
+                state.serialize_mods(xkb::STATE_MODS_EFFECTIVE) & 0xff
+            );
+        }
+    }
+}
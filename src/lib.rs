@@ -1,3 +1,13 @@
-mod render;
+pub mod render;
 pub mod config;
 pub mod app;
+pub mod navigator;
+mod screencopy;
+mod clipboard;
+mod screenshot;
+mod pointer;
+mod protocols;
+mod sway;
+mod windows;
+mod state;
+mod keyboard;
@@ -38,7 +38,13 @@ fn main() {
         }
     };
 
-    let mut app = AppRunner::init(config).unwrap();
+    let mut app = match AppRunner::init(config) {
+        Ok(app) => app,
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
 
     while app.pump() {}
 }
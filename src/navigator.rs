@@ -0,0 +1,962 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use log::{trace, warn};
+
+use crate::config::{
+    Anchor, ColorFormat, EdgePolicy, InitialRegion, KeyCombo, KeynavAction, Length, MouseButton,
+    Settings, TextFormat,
+};
+
+/// Used to resolve millimeter lengths when the physical size of the output is
+/// unknown (eg. projectors report 0x0).
+pub const DEFAULT_PIXELS_PER_MM: f64 = 96.0 / 25.4;
+
+// Slack for float comparisons against the edges of the screen
+const EPSILON: f64 = 1e-9;
+
+/// Fits `new` onto the unit square according to `policy`. `old` is the region
+/// that `new` replaces. Only moves should pass a policy other than
+/// [EdgePolicy::Clamp], as stopping or wrapping makes no sense for anything
+/// that resizes or places the region (eg. cursorzoom near a corner).
+pub fn fit_region(
+    old: cairo::Rectangle,
+    new: cairo::Rectangle,
+    policy: EdgePolicy,
+) -> cairo::Rectangle {
+    let width = f64::min(1.0, new.width);
+    let height = f64::min(1.0, new.height);
+    let outside = new.x < -EPSILON
+        || new.y < -EPSILON
+        || new.x + width > 1.0 + EPSILON
+        || new.y + height > 1.0 + EPSILON;
+    if policy == EdgePolicy::Stop && outside {
+        return old;
+    }
+    let fit_axis = |position: f64, size: f64, old_position: f64| {
+        if policy == EdgePolicy::Wrap {
+            if position + size > 1.0 + EPSILON && old_position + size >= 1.0 - EPSILON {
+                return 0.0;
+            } else if position < -EPSILON && old_position <= EPSILON {
+                return 1.0 - size;
+            }
+        }
+        position.clamp(0.0, 1.0 - size)
+    };
+    cairo::Rectangle {
+        x: fit_axis(new.x, width, old.x),
+        y: fit_axis(new.y, height, old.y),
+        width,
+        height,
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ButtonState {
+    Pressed,
+    Released,
+}
+
+/// Something the navigator needs done by whoever is driving the pointer and
+/// showing the overlay. Positions are normalized to the surface.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command {
+    Warp {
+        x: f64,
+        y: f64,
+    },
+    // button is a linux input event code (see [MouseButton::to_code])
+    Button {
+        button: u32,
+        state: ButtonState,
+    },
+    ToggleMagnifier,
+    // Sample the colour of the screen within `region` (see
+    // [KeynavAction::PickColor])
+    PickColor {
+        region: cairo::Rectangle,
+        format: ColorFormat,
+        average: bool,
+        copy: bool,
+    },
+    Screenshot {
+        region: cairo::Rectangle,
+        path: String,
+    },
+    Print(String),
+    Copy(String),
+    // Wait, having sent the commands before it
+    Sleep(Duration),
+    // Press a key or type text once the overlay is gone
+    Key(KeyCombo),
+    Type(String),
+    // Let the user pick one of the open windows
+    SelectWindow,
+    // Save `region` as the mark `name` of the current output
+    Mark {
+        name: String,
+        region: cairo::Rectangle,
+    },
+    End,
+}
+
+/// Tracks the active region and turns [KeynavAction]s into region updates
+/// and [Command]s, without knowing anything about wayland.
+#[derive(Debug, Clone)]
+pub struct Navigator {
+    region: cairo::Rectangle,
+    settings: Settings,
+    // In surface pixels
+    surface_size: (f64, f64),
+    pixels_per_mm: f64,
+    // In surface pixels, None until the pointer entered the surface
+    pointer: Option<(f64, f64)>,
+    pressed_buttons: HashSet<u32>,
+    // For %o in [TextFormat]s
+    output_name: String,
+    // Of the output in the compositor's global space, which positions in
+    // [TextFormat]s are relative to
+    output_position: (f64, f64),
+    // Of the current output, for goto-mark
+    marks: HashMap<String, cairo::Rectangle>,
+    // Where drag-start was, normalized, and with which button
+    drag_source: Option<((f64, f64), u32)>,
+}
+
+impl Navigator {
+    pub fn new(settings: Settings) -> Self {
+        Navigator {
+            region: cairo::Rectangle {
+                x: 0.0,
+                y: 0.0,
+                width: 1.0,
+                height: 1.0,
+            },
+            settings,
+            surface_size: (1.0, 1.0),
+            pixels_per_mm: DEFAULT_PIXELS_PER_MM,
+            pointer: None,
+            pressed_buttons: HashSet::new(),
+            output_name: String::new(),
+            output_position: (0.0, 0.0),
+            marks: HashMap::new(),
+            drag_source: None,
+        }
+    }
+    pub fn region(&self) -> cairo::Rectangle {
+        self.region
+    }
+    /// Sets the active region, clamped to the screen.
+    pub fn set_region(&mut self, rect: cairo::Rectangle) {
+        self.region = fit_region(self.region, rect, EdgePolicy::Clamp);
+    }
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+    /// Replaces the settings, eg. with those for the output we ended up on
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+    /// Puts the active region where the initial-region setting says it starts.
+    /// For [InitialRegion::Last] and [InitialRegion::FocusedWindow] that is
+    /// `remembered`, which the navigator can't know itself; without it the
+    /// region covers everything.
+    pub fn reset_region(&mut self, remembered: Option<cairo::Rectangle>) {
+        let (surface_width, surface_height) = self.surface_size;
+        let full = cairo::Rectangle {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        };
+        let rect = match self.settings.initial_region {
+            InitialRegion::Full => full,
+            InitialRegion::Last | InitialRegion::FocusedWindow => remembered.unwrap_or(full),
+            // Until we know where the pointer is, there is nothing to zoom to
+            InitialRegion::Cursor if self.pointer.is_none() => full,
+            InitialRegion::Cursor => {
+                let (width, height) = self.settings.cursorzoom_size;
+                self.region = full;
+                self.cursor_zoom(
+                    self.fraction_of(width, surface_width),
+                    self.fraction_of(height, surface_height),
+                );
+                return;
+            }
+            InitialRegion::Fixed {
+                x,
+                y,
+                width,
+                height,
+            } => cairo::Rectangle {
+                x: self.fraction_of(x, surface_width),
+                y: self.fraction_of(y, surface_height),
+                width: self.fraction_of(width, surface_width),
+                height: self.fraction_of(height, surface_height),
+            },
+        };
+        // Not a move, so the edge policy doesn't matter
+        self.region = fit_region(self.region, rect, EdgePolicy::Clamp);
+    }
+    pub fn set_surface_size(&mut self, width: u32, height: u32) {
+        self.surface_size = (width as f64, height as f64);
+    }
+    pub fn set_pixels_per_mm(&mut self, pixels_per_mm: f64) {
+        self.pixels_per_mm = pixels_per_mm;
+    }
+    /// Name of the output the surface is on, as it appears in print and copy
+    pub fn set_output_name(&mut self, name: &str) {
+        self.output_name = name.to_string();
+    }
+    /// Where the output the surface is on is in the compositor's global
+    /// space, so that print and copy give positions tools like grim understand
+    pub fn set_output_position(&mut self, x: i32, y: i32) {
+        self.output_position = (x as f64, y as f64);
+    }
+    /// The marks goto-mark can go to, which `mark` adds to
+    pub fn set_marks(&mut self, marks: HashMap<String, cairo::Rectangle>) {
+        self.marks = marks;
+    }
+    /// Position of the real pointer in surface pixels, used by cursorzoom.
+    pub fn set_pointer(&mut self, x: f64, y: f64) {
+        self.pointer = Some((x, y));
+    }
+
+    /// Runs `actions` in order and returns the commands they produce.
+    pub fn execute(&mut self, actions: &[KeynavAction]) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for action in actions {
+            self.execute_action(action, &mut commands);
+        }
+        commands
+    }
+
+    fn execute_action(&mut self, action: &KeynavAction, commands: &mut Vec<Command>) {
+        let (surface_width, surface_height) = self.surface_size;
+        let (region_width, region_height) = (
+            self.region.width * surface_width,
+            self.region.height * surface_height,
+        );
+        match action.clone() {
+            KeynavAction::CursorZoom(size) => {
+                trace!("Executing CenterCursor action");
+                let (width, height) = size.unwrap_or(self.settings.cursorzoom_size);
+                self.cursor_zoom(
+                    self.fraction_of(width, surface_width),
+                    self.fraction_of(height, surface_height),
+                );
+            }
+            KeynavAction::Region {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                trace!("Executing Region action");
+                self.set_region(cairo::Rectangle {
+                    x: self.fraction_of(x, surface_width),
+                    y: self.fraction_of(y, surface_height),
+                    width: self.fraction_of(width, surface_width),
+                    height: self.fraction_of(height, surface_height),
+                });
+            }
+            KeynavAction::MoveTo { x, y } => {
+                trace!("Executing MoveTo action");
+                self.move_to(
+                    self.fraction_of(x, surface_width),
+                    self.fraction_of(y, surface_height),
+                );
+            }
+            KeynavAction::CutRight(x) => {
+                trace!("Executing CutRight action");
+                self.cut_right(self.resolve(x, 0.5, region_width));
+            }
+            KeynavAction::CutLeft(x) => {
+                trace!("Executing CutLeft action");
+                self.cut_left(self.resolve(x, 0.5, region_width));
+            }
+            KeynavAction::CutUp(x) => {
+                trace!("Executing CutUp action");
+                self.cut_up(self.resolve(x, 0.5, region_height));
+            }
+            KeynavAction::CutDown(x) => {
+                trace!("Executing CutDown action");
+                self.cut_down(self.resolve(x, 0.5, region_height));
+            }
+            KeynavAction::MoveRight(x) => {
+                trace!("Executing MoveRight action");
+                self.move_by(self.resolve(x, 1.0, region_width), 0.0);
+            }
+            KeynavAction::MoveLeft(x) => {
+                trace!("Executing MoveLeft action");
+                self.move_by(-self.resolve(x, 1.0, region_width), 0.0);
+            }
+            KeynavAction::MoveUp(x) => {
+                trace!("Executing MoveUp action");
+                self.move_by(0.0, -self.resolve(x, 1.0, region_height));
+            }
+            KeynavAction::MoveDown(x) => {
+                trace!("Executing MoveDown action");
+                self.move_by(0.0, self.resolve(x, 1.0, region_height));
+            }
+            KeynavAction::Click(x) => {
+                trace!("Executing click action");
+                self.click(x.unwrap_or(MouseButton::Left).to_code(), commands);
+            }
+            KeynavAction::DragButton(x) => {
+                trace!("Executing drag button action");
+                self.drag(x.to_code(), commands);
+            }
+            KeynavAction::DragStart(x) => {
+                trace!("Executing drag start action");
+                let button = x.unwrap_or(MouseButton::Left).to_code();
+                self.drag_source = Some((self.center(), button));
+            }
+            KeynavAction::DragEnd => {
+                trace!("Executing drag end action");
+                match self.drag_source.take() {
+                    Some((source, button)) => self.drag_from(source, button, commands),
+                    None => warn!("drag-end without drag-start, not dragging"),
+                }
+            }
+            KeynavAction::DoubleClick(x) => {
+                trace!("Executing double click action");
+                let button = x.unwrap_or(MouseButton::Left).to_code();
+                self.click(button, commands);
+                if !self.settings.doubleclick_interval.is_zero() {
+                    commands.push(Command::Sleep(self.settings.doubleclick_interval));
+                }
+                self.click(button, commands);
+            }
+            KeynavAction::Hold(x, duration) => {
+                trace!("Executing hold action");
+                let button = x.to_code();
+                commands.push(self.warp_to(Anchor::Center));
+                commands.push(Command::Button {
+                    button,
+                    state: ButtonState::Pressed,
+                });
+                commands.push(Command::Sleep(duration));
+                commands.push(Command::Button {
+                    button,
+                    state: ButtonState::Released,
+                });
+            }
+            KeynavAction::Sleep(duration) => {
+                trace!("Executing sleep action");
+                commands.push(Command::Sleep(duration));
+            }
+            KeynavAction::Key(combo) => {
+                trace!("Executing key action");
+                commands.push(Command::Key(combo));
+            }
+            KeynavAction::Type(text) => {
+                trace!("Executing type action");
+                commands.push(Command::Type(text));
+            }
+            KeynavAction::Warp => {
+                trace!("Executing warp action");
+                commands.push(self.warp_to(Anchor::Center));
+            }
+            KeynavAction::WarpTo(anchor) => {
+                trace!("Executing warp to {:?} action", anchor);
+                commands.push(self.warp_to(anchor));
+            }
+            KeynavAction::Magnify => {
+                trace!("Executing magnify action");
+                commands.push(Command::ToggleMagnifier);
+            }
+            KeynavAction::WindowSelect => {
+                trace!("Executing window select action");
+                commands.push(Command::SelectWindow);
+            }
+            KeynavAction::Repeat => {
+                trace!("Ignoring repeat action, it should have been replaced by now");
+            }
+            KeynavAction::Mark(name) => {
+                trace!("Executing mark action");
+                self.marks.insert(name.clone(), self.region);
+                commands.push(Command::Mark {
+                    name,
+                    region: self.region,
+                });
+            }
+            KeynavAction::GotoMark { name, warp } => {
+                trace!("Executing goto-mark action");
+                match self.marks.get(&name) {
+                    Some(&region) => {
+                        self.region = fit_region(self.region, region, EdgePolicy::Clamp);
+                        if warp {
+                            commands.push(self.warp_to(Anchor::Center));
+                        }
+                    }
+                    None => warn!("There is no mark '{}' on this output", name),
+                }
+            }
+            KeynavAction::PickColor {
+                format,
+                average,
+                copy,
+            } => {
+                trace!("Executing pick color action");
+                commands.push(Command::PickColor {
+                    region: self.region,
+                    format,
+                    average,
+                    copy,
+                });
+            }
+            KeynavAction::Print(format) => {
+                trace!("Executing print action");
+                commands.push(Command::Print(self.describe(&format)));
+            }
+            KeynavAction::Copy(format) => {
+                trace!("Executing copy action");
+                commands.push(Command::Copy(self.describe(&format)));
+            }
+            KeynavAction::Screenshot(path) => {
+                trace!("Executing screenshot action");
+                commands.push(Command::Screenshot {
+                    region: self.region,
+                    path,
+                });
+            }
+            KeynavAction::End => {
+                trace!("Executing end action");
+                commands.push(Command::End);
+            }
+        }
+    }
+
+    // Fills in [format] with the geometry of the active region
+    fn describe(&self, format: &TextFormat) -> String {
+        let (surface_width, surface_height) = self.surface_size;
+        let (output_x, output_y) = self.output_position;
+        let region = self.region;
+        let pixels = |v: f64| (v.round() as i64).to_string();
+        format.expand(|placeholder| match placeholder {
+            'x' => pixels(output_x + region.x * surface_width),
+            'y' => pixels(output_y + region.y * surface_height),
+            'w' => pixels(region.width * surface_width),
+            'h' => pixels(region.height * surface_height),
+            'X' => pixels(output_x + (region.x + region.width / 2.0) * surface_width),
+            'Y' => pixels(output_y + (region.y + region.height / 2.0) * surface_height),
+            'o' => self.output_name.clone(),
+            _ => String::new(),
+        })
+    }
+
+    // Converts [length] to a fraction of [reference] (in surface pixels)
+    fn fraction_of(&self, length: Length, reference: f64) -> f64 {
+        length.to_fraction(reference, self.pixels_per_mm)
+    }
+    fn resolve(&self, length: Option<Length>, default: f64, reference: f64) -> f64 {
+        length.map_or(default, |length| self.fraction_of(length, reference))
+    }
+
+    fn warp_to(&self, anchor: Anchor) -> Command {
+        let (offset_x, offset_y) = anchor.offset();
+        // The right and bottom edges are one past the last pixel of the
+        // region, so stop on that pixel instead
+        let (pixel_width, pixel_height) = (1.0 / self.surface_size.0, 1.0 / self.surface_size.1);
+        let last_x = (self.region.x + self.region.width - pixel_width).max(self.region.x);
+        let last_y = (self.region.y + self.region.height - pixel_height).max(self.region.y);
+        Command::Warp {
+            x: (self.region.x + self.region.width * offset_x).min(last_x),
+            y: (self.region.y + self.region.height * offset_y).min(last_y),
+        }
+    }
+    fn click(&mut self, button: u32, commands: &mut Vec<Command>) {
+        commands.push(self.warp_to(Anchor::Center));
+        commands.push(Command::Button {
+            button,
+            state: ButtonState::Pressed,
+        });
+        commands.push(Command::Button {
+            button,
+            state: ButtonState::Released,
+        });
+    }
+    fn drag(&mut self, button: u32, commands: &mut Vec<Command>) {
+        let state = if self.pressed_buttons.remove(&button) {
+            ButtonState::Released
+        } else {
+            self.pressed_buttons.insert(button);
+            ButtonState::Pressed
+        };
+        commands.push(self.warp_to(Anchor::Center));
+        commands.push(Command::Button { button, state });
+    }
+    // Presses at `source`, moves to the center of the region in drag-steps
+    // steps and releases there
+    fn drag_from(&mut self, source: (f64, f64), button: u32, commands: &mut Vec<Command>) {
+        let (x0, y0) = source;
+        let (x1, y1) = self.center();
+        commands.push(Command::Warp { x: x0, y: y0 });
+        commands.push(Command::Button {
+            button,
+            state: ButtonState::Pressed,
+        });
+        let steps = self.settings.drag_steps.max(1);
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            commands.push(Command::Warp {
+                x: x0 + (x1 - x0) * t,
+                y: y0 + (y1 - y0) * t,
+            });
+        }
+        commands.push(Command::Button {
+            button,
+            state: ButtonState::Released,
+        });
+    }
+    fn center(&self) -> (f64, f64) {
+        (
+            self.region.x + self.region.width / 2.0,
+            self.region.y + self.region.height / 2.0,
+        )
+    }
+
+    // All arguments are normalized to the size of the surface
+    fn move_to(&mut self, x: f64, y: f64) {
+        let rect = self.region;
+        self.set_region(cairo::Rectangle {
+            x: x - rect.width / 2.0,
+            y: y - rect.height / 2.0,
+            width: rect.width,
+            height: rect.height,
+        });
+    }
+    fn cursor_zoom(&mut self, width: f64, height: f64) {
+        let (pointer_x, pointer_y) = self.pointer.unwrap_or_default();
+        let pointer_relative_x = pointer_x / self.surface_size.0;
+        let pointer_relative_y = pointer_y / self.surface_size.1;
+        self.set_region(cairo::Rectangle {
+            x: pointer_relative_x - width / 2.0,
+            y: pointer_relative_y - height / 2.0,
+            width,
+            height,
+        });
+    }
+    // dx and dy are fractions of the size of the region. The only thing the
+    // edge policy applies to.
+    fn move_by(&mut self, dx: f64, dy: f64) {
+        let rect = self.region;
+        self.region = fit_region(
+            rect,
+            cairo::Rectangle {
+                x: rect.x + rect.width * dx,
+                y: rect.y + rect.height * dy,
+                width: rect.width,
+                height: rect.height,
+            },
+            self.settings.edge_policy,
+        );
+    }
+    fn cut_left(&mut self, x: f64) {
+        let rect = self.region;
+        self.set_region(cairo::Rectangle {
+            width: rect.width * x,
+            ..rect
+        });
+    }
+    fn cut_right(&mut self, x: f64) {
+        let rect = self.region;
+        self.set_region(cairo::Rectangle {
+            x: rect.x + rect.width * (1.0 - x),
+            width: rect.width * x,
+            ..rect
+        });
+    }
+    fn cut_up(&mut self, x: f64) {
+        let rect = self.region;
+        self.set_region(cairo::Rectangle {
+            height: rect.height * x,
+            ..rect
+        });
+    }
+    fn cut_down(&mut self, x: f64) {
+        let rect = self.region;
+        self.set_region(cairo::Rectangle {
+            y: rect.y + rect.height * (1.0 - x),
+            height: rect.height * x,
+            ..rect
+        });
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::config::parse_actions;
+
+    #[allow(dead_code)]
+    fn rect(x: f64, y: f64, width: f64, height: f64) -> cairo::Rectangle {
+        cairo::Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn navigator() -> Navigator {
+        let mut navigator = Navigator::new(Settings::default());
+        navigator.set_surface_size(800, 600);
+        navigator.set_pixels_per_mm(4.0);
+        navigator
+    }
+
+    #[test]
+    fn cuts_and_moves() {
+        let mut navigator = navigator();
+        navigator.execute(&parse_actions("cut-left, cut-down").unwrap());
+        assert_eq!(rect(0.0, 0.5, 0.5, 0.5), navigator.region());
+        navigator.execute(&parse_actions("move-right, move-up 0.5").unwrap());
+        assert_eq!(rect(0.5, 0.25, 0.5, 0.5), navigator.region());
+    }
+
+    #[test]
+    fn cuts_resolve_units() {
+        let mut navigator = navigator();
+        navigator.execute(&parse_actions("cut-left 200px, cut-up 75mm").unwrap());
+        assert_eq!(rect(0.0, 0.0, 0.25, 0.5), navigator.region());
+    }
+
+    #[test]
+    fn cursorzoom_centers_on_pointer() {
+        let mut navigator = navigator();
+        navigator.set_pointer(400.0, 300.0);
+        navigator.execute(&parse_actions("cursorzoom 200 150").unwrap());
+        assert_eq!(rect(0.375, 0.375, 0.25, 0.25), navigator.region());
+    }
+
+    #[test]
+    fn settings_size_bare_cursorzoom() {
+        let mut navigator = navigator();
+        navigator.set_settings(Settings {
+            cursorzoom_size: (Length::Pixels(400.0), Length::Millimeters(75.0)),
+            ..Settings::default()
+        });
+        navigator.set_pointer(400.0, 300.0);
+        navigator.execute(&parse_actions("cursorzoom").unwrap());
+        assert_eq!(rect(0.25, 0.25, 0.5, 0.5), navigator.region());
+    }
+
+    #[test]
+    fn region_resets_to_initial_region() {
+        let mut navigator = navigator();
+        navigator.set_settings(Settings {
+            initial_region: InitialRegion::Fixed {
+                x: Length::Fraction(0.5),
+                y: Length::Pixels(0.0),
+                width: Length::Pixels(800.0),
+                height: Length::Fraction(0.5),
+            },
+            ..Settings::default()
+        });
+        navigator.reset_region(None);
+        // Clamped to stay on the surface
+        assert_eq!(rect(0.0, 0.0, 1.0, 0.5), navigator.region());
+
+        let remembered = Some(rect(0.5, 0.5, 0.25, 0.25));
+        navigator.set_pointer(0.0, 0.0);
+        for (initial_region, expected) in [
+            (InitialRegion::Full, rect(0.0, 0.0, 1.0, 1.0)),
+            (InitialRegion::Last, rect(0.5, 0.5, 0.25, 0.25)),
+            (InitialRegion::FocusedWindow, rect(0.5, 0.5, 0.25, 0.25)),
+            (InitialRegion::Cursor, rect(0.0, 0.0, 0.125, 1.0 / 6.0)),
+        ] {
+            navigator.set_settings(Settings {
+                initial_region,
+                ..Settings::default()
+            });
+            navigator.reset_region(remembered);
+            assert_eq!(expected, navigator.region(), "{:?}", initial_region);
+        }
+        // Nothing to remember, eg. on the first run
+        navigator.set_settings(Settings {
+            initial_region: InitialRegion::Last,
+            ..Settings::default()
+        });
+        navigator.reset_region(None);
+        assert_eq!(rect(0.0, 0.0, 1.0, 1.0), navigator.region());
+    }
+
+    #[test]
+    fn cursor_region_waits_for_pointer() {
+        let mut navigator = navigator();
+        navigator.set_settings(Settings {
+            initial_region: InitialRegion::Cursor,
+            ..Settings::default()
+        });
+        navigator.reset_region(None);
+        assert_eq!(rect(0.0, 0.0, 1.0, 1.0), navigator.region());
+        navigator.set_pointer(400.0, 300.0);
+        navigator.reset_region(None);
+        assert_eq!(
+            rect(0.4375, 5.0 / 12.0, 0.125, 1.0 / 6.0),
+            navigator.region()
+        );
+    }
+
+    #[test]
+    fn marks_are_remembered() {
+        let mut navigator = navigator();
+        let commands = navigator.execute(&parse_actions("cut-left, cut-up, mark a").unwrap());
+        assert_eq!(
+            vec![Command::Mark {
+                name: "a".into(),
+                region: rect(0.0, 0.0, 0.5, 0.5)
+            }],
+            commands
+        );
+        navigator.execute(&parse_actions("move-right, goto-mark b").unwrap());
+        assert_eq!(rect(0.5, 0.0, 0.5, 0.5), navigator.region());
+        let commands = navigator.execute(&parse_actions("goto-mark a warp").unwrap());
+        assert_eq!(rect(0.0, 0.0, 0.5, 0.5), navigator.region());
+        assert_eq!(vec![Command::Warp { x: 0.25, y: 0.25 }], commands);
+
+        let mut marks = HashMap::new();
+        marks.insert("b".to_string(), rect(0.25, 0.25, 0.5, 0.5));
+        navigator.set_marks(marks);
+        navigator.execute(&parse_actions("goto-mark b").unwrap());
+        assert_eq!(rect(0.25, 0.25, 0.5, 0.5), navigator.region());
+    }
+
+    #[test]
+    fn drag_moves_in_steps() {
+        let mut navigator = navigator();
+        navigator.set_settings(Settings {
+            drag_steps: 4,
+            ..Settings::default()
+        });
+        let commands = navigator.execute(
+            &parse_actions("cut-left, cut-up, drag-start 2, move-right, move-down, drag-end")
+                .unwrap(),
+        );
+        let button = |state| Command::Button {
+            button: 0x111,
+            state,
+        };
+        assert_eq!(
+            vec![
+                Command::Warp { x: 0.25, y: 0.25 },
+                button(ButtonState::Pressed),
+                Command::Warp { x: 0.375, y: 0.375 },
+                Command::Warp { x: 0.5, y: 0.5 },
+                Command::Warp { x: 0.625, y: 0.625 },
+                Command::Warp { x: 0.75, y: 0.75 },
+                button(ButtonState::Released),
+            ],
+            commands
+        );
+        // The drag is over
+        assert!(navigator
+            .execute(&parse_actions("drag-end").unwrap())
+            .is_empty());
+    }
+
+    #[test]
+    fn hold_and_doubleclick_wait() {
+        let mut navigator = navigator();
+        let press = |state| Command::Button {
+            button: 0x111,
+            state,
+        };
+        assert_eq!(
+            vec![
+                Command::Warp { x: 0.5, y: 0.5 },
+                press(ButtonState::Pressed),
+                Command::Sleep(Duration::from_millis(500)),
+                press(ButtonState::Released),
+                Command::Sleep(Duration::from_millis(20)),
+            ],
+            navigator.execute(&parse_actions("hold 2 500, sleep 20").unwrap())
+        );
+
+        navigator.set_settings(Settings {
+            doubleclick_interval: Duration::from_millis(300),
+            ..Settings::default()
+        });
+        let commands = navigator.execute(&parse_actions("doubleclick 2").unwrap());
+        assert_eq!(7, commands.len());
+        assert_eq!(Command::Sleep(Duration::from_millis(300)), commands[3]);
+    }
+
+    #[test]
+    fn click_warps_then_presses() {
+        let mut navigator = navigator();
+        let commands = navigator.execute(&parse_actions("cut-left, click 1, end").unwrap());
+        assert_eq!(
+            vec![
+                Command::Warp { x: 0.25, y: 0.5 },
+                Command::Button {
+                    button: 0x110,
+                    state: ButtonState::Pressed
+                },
+                Command::Button {
+                    button: 0x110,
+                    state: ButtonState::Released
+                },
+                Command::End,
+            ],
+            commands
+        );
+    }
+
+    #[test]
+    fn drag_toggles_button() {
+        let mut navigator = navigator();
+        let drag = parse_actions("drag 1").unwrap();
+        assert_eq!(
+            Command::Button {
+                button: 0x110,
+                state: ButtonState::Pressed
+            },
+            navigator.execute(&drag)[1]
+        );
+        assert_eq!(
+            Command::Button {
+                button: 0x110,
+                state: ButtonState::Released
+            },
+            navigator.execute(&drag)[1]
+        );
+    }
+
+    #[test]
+    fn warp_to_anchor() {
+        let mut navigator = navigator();
+        // On the last pixel of the 800x600 surface rather than past it
+        assert_eq!(
+            vec![Command::Warp {
+                x: 1.0 - 1.0 / 800.0,
+                y: 0.5
+            }],
+            navigator.execute(&parse_actions("warp-to right").unwrap())
+        );
+        assert_eq!(
+            vec![Command::Warp {
+                x: 0.5 - 1.0 / 800.0,
+                y: 1.0 - 1.0 / 600.0
+            }],
+            navigator.execute(&parse_actions("cut-left, warp-to bottom-right").unwrap())
+        );
+        assert_eq!(
+            vec![Command::Warp { x: 0.0, y: 0.0 }],
+            navigator.execute(&parse_actions("warp-to top-left").unwrap())
+        );
+    }
+
+    #[test]
+    fn print_and_copy_describe_region() {
+        let mut navigator = navigator();
+        navigator.set_output_name("DP-1");
+        assert_eq!(
+            vec![
+                Command::Print("400,0 400x300".into()),
+                Command::Copy("600 150 on DP-1".into())
+            ],
+            navigator.execute(
+                &parse_actions("cut-right, cut-up, print geometry, copy %X %Y on %o").unwrap()
+            )
+        );
+
+        // Positions are in the compositor's global space, sizes aren't
+        navigator.set_output_position(1920, 40);
+        assert_eq!(
+            vec![
+                Command::Print("2320,40 400x300".into()),
+                Command::Print("2520 190".into())
+            ],
+            navigator.execute(&parse_actions("print geometry, print %X %Y").unwrap())
+        );
+    }
+
+    #[test]
+    fn magnify_leaves_region_alone() {
+        let mut navigator = navigator();
+        let region = navigator.region();
+        assert_eq!(
+            vec![Command::ToggleMagnifier],
+            navigator.execute(&parse_actions("magnify").unwrap())
+        );
+        assert_eq!(region, navigator.region());
+    }
+
+    #[test]
+    fn clamp_slides_region_inside() {
+        let old = rect(0.5, 0.5, 0.5, 0.5);
+        assert_eq!(
+            rect(0.5, 0.5, 0.5, 0.5),
+            fit_region(old, rect(1.0, 0.5, 0.5, 0.5), EdgePolicy::Clamp)
+        );
+        let old = rect(0.0, 0.0, 0.25, 0.25);
+        assert_eq!(
+            rect(0.0, 0.0, 0.25, 0.25),
+            fit_region(old, rect(-0.25, -0.125, 0.25, 0.25), EdgePolicy::Clamp)
+        );
+    }
+
+    #[test]
+    fn clamp_limits_size_to_screen() {
+        let old = rect(0.0, 0.0, 1.0, 1.0);
+        assert_eq!(
+            rect(0.0, 0.0, 1.0, 1.0),
+            fit_region(old, rect(-0.5, 0.0, 2.0, 1.0), EdgePolicy::Clamp)
+        );
+    }
+
+    #[test]
+    fn stop_ignores_moves_past_edge() {
+        let old = rect(0.75, 0.0, 0.25, 0.25);
+        assert_eq!(
+            old,
+            fit_region(old, rect(1.0, 0.0, 0.25, 0.25), EdgePolicy::Stop)
+        );
+        assert_eq!(
+            rect(0.5, 0.0, 0.25, 0.25),
+            fit_region(old, rect(0.5, 0.0, 0.25, 0.25), EdgePolicy::Stop)
+        );
+    }
+
+    #[test]
+    fn stop_only_applies_to_moves() {
+        let mut navigator = navigator();
+        navigator.set_settings(Settings {
+            edge_policy: EdgePolicy::Stop,
+            ..Settings::default()
+        });
+        navigator.execute(&parse_actions("cut-right, move-right").unwrap());
+        assert_eq!(rect(0.5, 0.0, 0.5, 1.0), navigator.region());
+        // Placing the region is clamped, even when it keeps its size
+        navigator.execute(&parse_actions("region 0 0 50% 100%, move-to 100% 50%").unwrap());
+        assert_eq!(rect(0.5, 0.0, 0.5, 1.0), navigator.region());
+        navigator.set_pointer(790.0, 590.0);
+        navigator.execute(&parse_actions("cursorzoom 80 60").unwrap());
+        assert_eq!(rect(0.9, 0.9, 0.1, 0.1), navigator.region());
+    }
+
+    #[test]
+    fn wrap_slides_to_edge_then_wraps() {
+        let old = rect(0.5, 0.0, 0.25, 0.25);
+        let at_edge = fit_region(old, rect(0.875, 0.0, 0.25, 0.25), EdgePolicy::Wrap);
+        assert_eq!(rect(0.75, 0.0, 0.25, 0.25), at_edge);
+        assert_eq!(
+            rect(0.0, 0.0, 0.25, 0.25),
+            fit_region(at_edge, rect(1.0, 0.0, 0.25, 0.25), EdgePolicy::Wrap)
+        );
+    }
+
+    #[test]
+    fn wrap_moves_to_opposite_edge() {
+        let old = rect(0.0, 0.0, 0.25, 0.5);
+        assert_eq!(
+            rect(0.75, 0.5, 0.25, 0.5),
+            fit_region(old, rect(-0.25, -0.5, 0.25, 0.5), EdgePolicy::Wrap)
+        );
+    }
+}
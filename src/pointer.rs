@@ -0,0 +1,151 @@
+use log::*;
+
+use wayland_client::{protocol::wl_pointer, protocol::wl_seat, GlobalManager, Main};
+use wayland_protocols::wlr::unstable::virtual_pointer::v1::client::{
+    zwlr_virtual_pointer_manager_v1, zwlr_virtual_pointer_v1,
+};
+
+use crate::config::PointerBackendChoice;
+use crate::navigator::ButtonState;
+use crate::protocols::fake_input::client::org_kde_kwin_fake_input;
+
+/// Something that can move the pointer and click for us.
+pub trait PointerBackend {
+    /// Moves the pointer to `x`, `y`, normalized to our surface, which covers
+    /// `output` (x, y, width and height in the compositor's global space).
+    fn warp(&self, x: f64, y: f64, output: (i32, i32, i32, i32));
+    /// Presses or releases `button`, an evdev code like BTN_LEFT.
+    fn button(&self, button: u32, state: ButtonState);
+}
+
+/// Milliseconds on the monotonic clock, which is what compositors timestamp
+/// input events with.
+pub(crate) fn now_ms() -> u32 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    (time.tv_sec as u64 * 1000 + time.tv_nsec as u64 / 1_000_000) as u32
+}
+
+/// zwlr_virtual_pointer_v1, as supported by wlroots based compositors.
+pub struct WlrVirtualPointer {
+    pointer: Main<zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1>,
+}
+
+impl PointerBackend for WlrVirtualPointer {
+    fn warp(&self, x: f64, y: f64, _output: (i32, i32, i32, i32)) {
+        // We scale by extent because motion_absolute takes ints, but we have
+        // normalized scalar coords so we are bsaically just converting the
+        // float to a fixed point with 4 decimal places
+        let extent = 10000;
+        self.pointer.motion_absolute(
+            now_ms(),
+            (x * extent as f64) as u32,
+            (y * extent as f64) as u32,
+            extent,
+            extent,
+        );
+        self.pointer.frame();
+    }
+    fn button(&self, button: u32, state: ButtonState) {
+        let state = match state {
+            ButtonState::Pressed => wl_pointer::ButtonState::Pressed,
+            ButtonState::Released => wl_pointer::ButtonState::Released,
+        };
+        self.pointer.button(now_ms(), button, state);
+        self.pointer.frame();
+    }
+}
+
+/// org_kde_kwin_fake_input, as supported by KWin. It works in global
+/// coordinates rather than relative to an output.
+pub struct KdeFakeInput {
+    fake_input: Main<org_kde_kwin_fake_input::OrgKdeKwinFakeInput>,
+}
+
+impl PointerBackend for KdeFakeInput {
+    fn warp(&self, x: f64, y: f64, output: (i32, i32, i32, i32)) {
+        let (output_x, output_y, width, height) = output;
+        self.fake_input.pointer_motion_absolute(
+            output_x as f64 + x * width as f64,
+            output_y as f64 + y * height as f64,
+        );
+    }
+    fn button(&self, button: u32, state: ButtonState) {
+        // Same values as wl_pointer.button_state
+        let state = match state {
+            ButtonState::Pressed => 1,
+            ButtonState::Released => 0,
+        };
+        self.fake_input.button(button, state);
+    }
+}
+
+/// Logs what it would do instead of doing it.
+pub struct DryRun;
+
+impl PointerBackend for DryRun {
+    fn warp(&self, x: f64, y: f64, output: (i32, i32, i32, i32)) {
+        info!("Dry run: warp to {:.4}, {:.4} of {:?}", x, y, output);
+    }
+    fn button(&self, button: u32, state: ButtonState) {
+        info!("Dry run: button {:#x} {:?}", button, state);
+    }
+}
+
+fn wlr_virtual_pointer(
+    globals: &GlobalManager,
+    seat: &wl_seat::WlSeat,
+) -> Option<Box<dyn PointerBackend>> {
+    let manager = globals
+        .instantiate_range::<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1>(1, 2)
+        .ok()?;
+    Some(Box::new(WlrVirtualPointer {
+        pointer: manager.create_virtual_pointer(Some(seat)),
+    }))
+}
+
+fn kde_fake_input(globals: &GlobalManager) -> Option<Box<dyn PointerBackend>> {
+    // Absolute motion is new in version 3
+    let fake_input = globals
+        .instantiate_range::<org_kde_kwin_fake_input::OrgKdeKwinFakeInput>(3, 4)
+        .ok()?;
+    fake_input.authenticate(
+        "keynav-wayland".into(),
+        "Move the pointer and click with the keyboard".into(),
+    );
+    Some(Box::new(KdeFakeInput { fake_input }))
+}
+
+/// Sets up the backend picked in the settings, or with
+/// [PointerBackendChoice::Auto] the first one the compositor supports.
+pub fn select(
+    choice: PointerBackendChoice,
+    globals: &GlobalManager,
+    seat: &wl_seat::WlSeat,
+) -> Result<Box<dyn PointerBackend>, String> {
+    let unsupported = |interface: &str| {
+        format!(
+            "pointer-backend is set to {:?}, but the compositor does not support {}",
+            choice, interface
+        )
+    };
+    match choice {
+        PointerBackendChoice::Auto => Ok(wlr_virtual_pointer(globals, seat)
+            .or_else(|| kde_fake_input(globals))
+            .unwrap_or_else(|| {
+                warn!(
+                    "Compositor supports neither zwlr_virtual_pointer_manager_v1 nor \
+                     org_kde_kwin_fake_input (version 3), clicks and warps won't do anything"
+                );
+                Box::new(DryRun)
+            })),
+        PointerBackendChoice::WlrVirtualPointer => wlr_virtual_pointer(globals, seat)
+            .ok_or_else(|| unsupported("zwlr_virtual_pointer_manager_v1")),
+        PointerBackendChoice::KdeFakeInput => kde_fake_input(globals)
+            .ok_or_else(|| unsupported("org_kde_kwin_fake_input (version 3)")),
+        PointerBackendChoice::DryRun => Ok(Box::new(DryRun)),
+    }
+}
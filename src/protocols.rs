@@ -0,0 +1,41 @@
+//! Protocols that wayland-protocols doesn't ship, generated by build.rs from
+//! the XML in protocols/
+
+pub mod fake_input {
+    #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+    #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+    #![allow(missing_docs, clippy::all)]
+    // wayland-scanner 0.29 takes references to the `static mut` interface
+    // tables, which static_mut_refs warns about since Rust 1.83 (older
+    // toolchains don't know the lint, hence unknown_lints)
+    #![allow(unknown_lints, static_mut_refs)]
+
+    pub mod client {
+        pub(crate) use wayland_client::sys;
+        pub(crate) use wayland_client::{AnonymousObject, Attached, Main, Proxy, ProxyMap};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::smallvec;
+        pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        include!(concat!(env!("OUT_DIR"), "/fake_input_client_api.rs"));
+    }
+}
+
+pub mod virtual_keyboard {
+    #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+    #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+    #![allow(missing_docs, clippy::all)]
+    // See fake_input
+    #![allow(unknown_lints, static_mut_refs)]
+
+    pub mod client {
+        pub(crate) use wayland_client::protocol::wl_seat;
+        pub(crate) use wayland_client::sys;
+        pub(crate) use wayland_client::{AnonymousObject, Attached, Main, Proxy, ProxyMap};
+        pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+        pub(crate) use wayland_commons::smallvec;
+        pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+        pub(crate) use wayland_commons::{Interface, MessageGroup};
+        include!(concat!(env!("OUT_DIR"), "/virtual_keyboard_client_api.rs"));
+    }
+}
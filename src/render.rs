@@ -1,12 +1,68 @@
 use std::fs::File;
+use std::io::Write;
 use std::os::unix::io::AsRawFd;
 use std::rc::Rc;
+use std::time::Duration;
 
-use log::trace;
+use crate::config::{Color, Easing, Settings};
+
+// Rectangle in buffer pixels as (x, y, width, height), the same as
+// wl_surface.damage_buffer takes
+pub type Damage = (i32, i32, i32, i32);
+
+// Largest size of the magnifier, in surface pixels
+const LOUPE_SIZE: f64 = 176.0;
+// Between the active region and the magnifier
+const LOUPE_GAP: f64 = 8.0;
+// How many captured pixels the magnifier shows across, at least and at most.
+// Within that it shows the whole active region.
+const LOUPE_MIN_PIXELS: i32 = 9;
+const LOUPE_MAX_PIXELS: i32 = 41;
+
+// Of the window list, in surface pixels
+const LIST_FONT_SIZE: f64 = 16.0;
+const LIST_LINE_HEIGHT: f64 = 24.0;
+const LIST_PADDING: f64 = 12.0;
+
+// Everything that decides what ends up in the buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Drawn {
+    region: cairo::Rectangle,
+    magnified: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Animation {
+    from: cairo::Rectangle,
+    to: cairo::Rectangle,
+    // Time of the first frame of the animation, in the milliseconds of
+    // wl_callback.done
+    start: Option<u32>,
+}
 
 #[derive(Clone)]
 pub struct RenderManager {
+    // What is shown, which lags behind the latest region while animating
     active_region: cairo::Rectangle,
+    animation: Option<Animation>,
+    animation_duration: Duration,
+    animation_easing: Easing,
+    fill_color: Color,
+    border_color: Color,
+    // What is currently in the buffer, None if the buffer needs to be redrawn
+    // from scratch
+    drawn: Option<Drawn>,
+    damage: Vec<Damage>,
+    // The output as it was before we covered it, shown magnified next to the
+    // active region
+    magnifier: Option<cairo::ImageSurface>,
+    // Labels and descriptions of windows to pick from
+    window_list: Option<Vec<(String, String)>>,
+    // Name of the profile in use, shown in the corner
+    profile: Option<String>,
+    // Buffer pixels per surface pixel, as set with wl_surface.set_buffer_scale
+    scale: u32,
+    // Of the buffer, ie. the surface size times [RenderManager.scale]
     height: u32,
     width: u32,
     stride: i32,
@@ -26,10 +82,21 @@ impl RenderManager {
                 x: 0.0,
                 y: 0.0,
             },
+            animation: None,
+            animation_duration: Duration::ZERO,
+            animation_easing: Easing::Linear,
+            fill_color: Settings::default().fill_color,
+            border_color: Settings::default().border_color,
+            drawn: None,
+            damage: Vec::new(),
+            magnifier: None,
+            window_list: None,
+            profile: None,
+            scale: 1,
             height: 0,
             width: 0,
             stride: -1,
-            format: format,
+            format,
             temp: Rc::new(
                 tempfile::tempfile().map_err(|_| "Failed to create temporary backing file")?,
             ),
@@ -38,10 +105,9 @@ impl RenderManager {
         renderer.set_bounds(width, height)?;
         Ok(renderer)
     }
-    pub fn device_to_user(&self, x: f64, y: f64) -> (f64, f64) {
-        self.cairo_context.device_to_user(x,y).unwrap()
-    }
+    /// Resize the buffer for a surface of `width` by `height` surface pixels
     pub fn set_bounds(&mut self, width: u32, height: u32) -> Result<(), String> {
+        let (width, height) = (width * self.scale, height * self.scale);
         self.stride = self
             .format
             .stride_for_width(width)
@@ -74,9 +140,256 @@ impl RenderManager {
             .scale(self.get_width() as f64, self.get_height() as f64);
         self.cairo_context.set_operator(cairo::Operator::Source);
 
+        // Damage from before the resize refers to the old buffer
+        self.damage.clear();
+        self.invalidate();
         self.redraw()
     }
-    pub fn redraw(&self) -> Result<(), String> {
+    /// Draw at `scale` buffer pixels per surface pixel, for outputs with that
+    /// scale. Lines stay a single buffer pixel wide, everything else grows
+    /// with the scale.
+    pub fn set_scale(&mut self, scale: u32) -> Result<(), String> {
+        let (width, height) = self.get_surface_size();
+        self.scale = scale.max(1);
+        self.set_bounds(width, height)
+    }
+    pub fn get_scale(&self) -> u32 {
+        self.scale
+    }
+    /// In surface pixels, ie. the buffer size divided by the scale
+    pub fn get_surface_size(&self) -> (u32, u32) {
+        (self.width / self.scale, self.height / self.scale)
+    }
+    /// Forget what is in the buffer so that the next [RenderManager::redraw]
+    /// repaints all of it
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+    /// Repaint the part of the buffer that changed since the last redraw and
+    /// record it in the damage returned by [RenderManager::take_damage]
+    pub fn redraw(&mut self) -> Result<(), String> {
+        let now = Drawn {
+            region: self.active_region,
+            magnified: self.magnifier.is_some(),
+        };
+        let damage = match self.drawn {
+            Some(drawn) if drawn == now => return Ok(()),
+            // Each on its own rather than what surrounds them, which is most
+            // of the buffer when the region moves across it
+            Some(drawn) => {
+                let mut extents = self.extents(drawn);
+                extents.extend(self.extents(now));
+                without_contained(extents)
+            }
+            None => vec![(0, 0, self.width as i32, self.height as i32)],
+        };
+        let damage: Vec<Damage> = damage
+            .into_iter()
+            .filter(|(_, _, width, height)| *width > 0 && *height > 0)
+            .collect();
+        if damage.is_empty() {
+            return Ok(());
+        }
+
+        // Clip in device space so that the repainted area is pixel aligned
+        let initial_matrix = self.cairo_context.matrix();
+        self.cairo_context.identity_matrix();
+        for (x, y, width, height) in &damage {
+            self.cairo_context
+                .rectangle(*x as f64, *y as f64, *width as f64, *height as f64);
+        }
+        self.cairo_context.set_matrix(initial_matrix);
+        self.cairo_context.clip();
+        let result = self.draw();
+        self.cairo_context.reset_clip();
+        result?;
+
+        self.drawn = Some(now);
+        self.damage.extend(damage);
+        Ok(())
+    }
+    /// Everything repainted since the last call, in buffer pixels
+    pub fn take_damage(&mut self) -> Vec<Damage> {
+        std::mem::take(&mut self.damage)
+    }
+    // Pixels touched when drawing `drawn`: around the region, and the
+    // magnifier if it is shown
+    fn extents(&self, drawn: Drawn) -> Vec<Damage> {
+        let mut extents = vec![self.bounds(drawn.region)];
+        if drawn.magnified {
+            let (x, y, size) = self.loupe_rect(drawn.region);
+            extents.push((x as i32 - 1, y as i32 - 1, size as i32 + 2, size as i32 + 2));
+        }
+        extents
+    }
+    // Pixels touched when drawing `region`, including the half of the border
+    // lines that falls outside of it
+    fn bounds(&self, region: cairo::Rectangle) -> Damage {
+        let (width, height) = (self.width as f64, self.height as f64);
+        let x0 = ((region.x * width).floor() as i32 - 1).max(0);
+        let y0 = ((region.y * height).floor() as i32 - 1).max(0);
+        let x1 = (((region.x + region.width) * width).ceil() as i32 + 1).min(self.width as i32);
+        let y1 = (((region.y + region.height) * height).ceil() as i32 + 1).min(self.height as i32);
+        (x0, y0, x1 - x0, y1 - y0)
+    }
+    // Where the magnifier goes for `region`: a square (x, y, size) in buffer
+    // pixels, beside the region on whichever side has room
+    fn loupe_rect(&self, region: cairo::Rectangle) -> (f64, f64, f64) {
+        let (width, height) = (self.width as f64, self.height as f64);
+        let scale = self.scale as f64;
+        let size = (LOUPE_SIZE * scale).min((width.min(height) / 2.0).floor());
+        let right = ((region.x + region.width) * width + LOUPE_GAP * scale).ceil();
+        let x = if right + size <= width {
+            right
+        } else {
+            (region.x * width - LOUPE_GAP * scale - size).floor()
+        };
+        let x = x.clamp(0.0, width - size);
+        let y = (region.y * height).round().clamp(0.0, height - size);
+        (x, y, size)
+    }
+    fn draw_magnifier(&self, capture: &cairo::ImageSurface) -> Result<(), String> {
+        let (x, y, size) = self.loupe_rect(self.active_region);
+        let region = self.active_region;
+        let (capture_width, capture_height) = (capture.width() as f64, capture.height() as f64);
+        // An odd number of pixels, so that the one under the center of the
+        // region is in the middle
+        let pixels = (region.width * capture_width)
+            .max(region.height * capture_height)
+            .round() as i32;
+        let pixels = pixels.clamp(LOUPE_MIN_PIXELS, LOUPE_MAX_PIXELS) / 2 * 2 + 1;
+        let center_x = ((region.x + region.width / 2.0) * capture_width).floor();
+        let center_y = ((region.y + region.height / 2.0) * capture_height).floor();
+        let half = (pixels / 2) as f64;
+        let zoom = size / pixels as f64;
+
+        let context = &self.cairo_context;
+        context.save().map_err(|err| err.to_string())?;
+        context.identity_matrix();
+        context.translate(x, y);
+        context.scale(zoom, zoom);
+        let pixels = pixels as f64;
+
+        // Background for anything beyond the edges of the capture
+        context.set_source_rgb(0.0, 0.0, 0.0);
+        context.rectangle(0.0, 0.0, pixels, pixels);
+        context.fill().map_err(|err| err.to_string())?;
+
+        context.set_operator(cairo::Operator::Over);
+        context
+            .set_source_surface(capture, half - center_x, half - center_y)
+            .map_err(|err| err.to_string())?;
+        context.source().set_filter(cairo::Filter::Nearest);
+        context.rectangle(0.0, 0.0, pixels, pixels);
+        context.fill().map_err(|err| err.to_string())?;
+
+        if zoom >= 4.0 {
+            context.set_source_rgba(0.0, 0.0, 0.0, 0.25);
+            context.set_line_width(1.0 / zoom);
+            for i in 1..pixels as i32 {
+                context.move_to(i as f64, 0.0);
+                context.line_to(i as f64, pixels);
+                context.move_to(0.0, i as f64);
+                context.line_to(pixels, i as f64);
+            }
+            context.stroke().map_err(|err| err.to_string())?;
+        }
+
+        // The pixel a click would land on
+        context.set_source_rgb(1.0, 0.0, 0.0);
+        context.set_line_width(2.0 / zoom);
+        context.rectangle(half, half, 1.0, 1.0);
+        context.stroke().map_err(|err| err.to_string())?;
+
+        context.set_source_rgb(0.0, 0.0, 0.0);
+        context.set_line_width(2.0 / zoom);
+        context.rectangle(0.0, 0.0, pixels, pixels);
+        context.stroke().map_err(|err| err.to_string())?;
+
+        context.restore().map_err(|err| err.to_string())
+    }
+    // A panel in the middle of the surface with a line per window
+    fn draw_window_list(&self, entries: &[(String, String)]) -> Result<(), String> {
+        let context = &self.cairo_context;
+        context.save().map_err(|err| err.to_string())?;
+        context.identity_matrix();
+        context.scale(self.scale as f64, self.scale as f64);
+        context.select_font_face(
+            "sans-serif",
+            cairo::FontSlant::Normal,
+            cairo::FontWeight::Bold,
+        );
+        context.set_font_size(LIST_FONT_SIZE);
+        let advance = |text: &str| {
+            context
+                .text_extents(text)
+                .map(|extents| extents.x_advance)
+                .map_err(|err| err.to_string())
+        };
+        let mut label_width: f64 = 0.0;
+        let mut text_width: f64 = 0.0;
+        for (label, text) in entries {
+            label_width = label_width.max(advance(label)?);
+            text_width = text_width.max(advance(text)?);
+        }
+        let (surface_width, surface_height) = self.get_surface_size();
+        let (surface_width, surface_height) = (surface_width as f64, surface_height as f64);
+        let width = (label_width + text_width + 3.0 * LIST_PADDING)
+            .min(surface_width - 2.0 * LIST_PADDING)
+            .max(0.0);
+        let height = (entries.len() as f64 * LIST_LINE_HEIGHT + 2.0 * LIST_PADDING)
+            .min(surface_height - 2.0 * LIST_PADDING)
+            .max(0.0);
+        let x = ((surface_width - width) / 2.0).floor();
+        let y = ((surface_height - height) / 2.0).floor();
+
+        context.set_source_rgba(0.1, 0.1, 0.1, 0.9);
+        context.rectangle(x, y, width, height);
+        context.fill_preserve().map_err(|err| err.to_string())?;
+        // Whatever doesn't fit is cut off
+        context.clip();
+        for (i, (label, text)) in entries.iter().enumerate() {
+            let baseline = y + LIST_PADDING + (i as f64 + 0.75) * LIST_LINE_HEIGHT;
+            context.set_source_rgb(1.0, 0.85, 0.2);
+            context.move_to(x + LIST_PADDING, baseline);
+            context.show_text(label).map_err(|err| err.to_string())?;
+            context.set_source_rgb(1.0, 1.0, 1.0);
+            context.move_to(x + 2.0 * LIST_PADDING + label_width, baseline);
+            context.show_text(text).map_err(|err| err.to_string())?;
+        }
+        context.restore().map_err(|err| err.to_string())?;
+        Ok(())
+    }
+    // The name of the profile in the top left corner
+    fn draw_profile(&self, name: &str) -> Result<(), String> {
+        let context = &self.cairo_context;
+        context.save().map_err(|err| err.to_string())?;
+        context.identity_matrix();
+        context.scale(self.scale as f64, self.scale as f64);
+        context.select_font_face(
+            "sans-serif",
+            cairo::FontSlant::Normal,
+            cairo::FontWeight::Bold,
+        );
+        context.set_font_size(LIST_FONT_SIZE);
+        let extents = context.text_extents(name).map_err(|err| err.to_string())?;
+        context.set_source_rgba(0.1, 0.1, 0.1, 0.9);
+        context.rectangle(
+            LIST_PADDING,
+            LIST_PADDING,
+            (extents.x_advance + 2.0 * LIST_PADDING).ceil(),
+            LIST_LINE_HEIGHT + LIST_PADDING,
+        );
+        context.fill().map_err(|err| err.to_string())?;
+        context.set_source_rgb(1.0, 1.0, 1.0);
+        context.move_to(
+            2.0 * LIST_PADDING,
+            1.5 * LIST_PADDING + 0.75 * LIST_LINE_HEIGHT,
+        );
+        context.show_text(name).map_err(|err| err.to_string())?;
+        context.restore().map_err(|err| err.to_string())
+    }
+    fn draw(&self) -> Result<(), String> {
         self.cairo_context.set_source_rgba(0.0, 0.0, 0.0, 0.0);
         self.cairo_context.rectangle(0.0, 0.0, 1.0, 1.0);
         self.cairo_context.fill().map_err(|err| err.to_string())?;
@@ -87,11 +400,13 @@ impl RenderManager {
         self.cairo_context
             .scale(self.active_region.width, self.active_region.height);
 
-        self.cairo_context.set_source_rgba(1.0, 1.0, 1.0, 0.2);
+        let Color { r, g, b, a } = self.fill_color;
+        self.cairo_context.set_source_rgba(r, g, b, a);
         self.cairo_context.rectangle(0.0, 0.0, 1.0, 1.0);
         self.cairo_context.fill().map_err(|err| err.to_string())?;
 
-        self.cairo_context.set_source_rgb(0.0, 0.0, 0.0);
+        let Color { r, g, b, a } = self.border_color;
+        self.cairo_context.set_source_rgba(r, g, b, a);
         let (line_width_x, line_width_y) = self
             .cairo_context
             .device_to_user_distance(1.0, 1.0)
@@ -125,22 +440,115 @@ impl RenderManager {
 
         self.cairo_context.set_matrix(initial_matrix);
 
+        if let Some(capture) = &self.magnifier {
+            self.draw_magnifier(capture)?;
+        }
+        if let Some(name) = &self.profile {
+            self.draw_profile(name)?;
+        }
+        if let Some(entries) = &self.window_list {
+            self.draw_window_list(entries)?;
+        }
+
         Ok(())
     }
+    /// Encode the current contents of the buffer as a PNG. This only touches
+    /// the in-memory buffer, so it works without a compositor.
+    pub fn write_png<W: Write>(&self, stream: &mut W) -> Result<(), String> {
+        let surface = cairo::ImageSurface::try_from(self.cairo_context.target())
+            .map_err(|_| "Render target is not an image surface")?;
+        surface.flush();
+        surface.write_to_png(stream).map_err(|err| err.to_string())
+    }
+    /// Animate changes of the active region over `duration`, or not at all if
+    /// it is zero
+    pub fn set_animation(&mut self, duration: Duration, easing: Easing) {
+        self.animation_duration = duration;
+        self.animation_easing = easing;
+    }
+    /// Of the active region and its outline
+    pub fn set_colors(&mut self, fill: Color, border: Color) {
+        if (fill, border) != (self.fill_color, self.border_color) {
+            self.fill_color = fill;
+            self.border_color = border;
+            self.invalidate();
+        }
+    }
+    /// Shows `rect` straight away, even if changes are otherwise animated
+    pub fn jump_to_region(&mut self, rect: cairo::Rectangle) {
+        self.active_region = rect;
+        self.animation = None;
+    }
     pub fn update_active_region(&mut self, rect: cairo::Rectangle) {
-        let (dx, dy) = self
-            .cairo_context
-            .user_to_device_distance(rect.width, rect.height)
-            .unwrap();
-        trace!("dx, dy: {}, {}", dx, dy);
-        self.active_region = cairo::Rectangle {
-            x: f64::max(0.0, rect.x),
-            y: f64::max(0.0, rect.y),
-            height: f64::min(1.0, rect.height),
-            width: f64::min(1.0, rect.width),
-        };
+        let target = self
+            .animation
+            .map_or(self.active_region, |animation| animation.to);
+        if rect == target {
+            return;
+        }
+        if self.animation_duration.is_zero() {
+            self.active_region = rect;
+            self.animation = None;
+        } else {
+            self.animation = Some(Animation {
+                from: self.active_region,
+                to: rect,
+                start: None,
+            });
+        }
+    }
+    /// The region as it should be drawn at `time`, the timestamp of a frame
+    /// callback. An animation starts at the first time it is ticked.
+    pub fn tick(&mut self, time: u32) {
+        if let Some(animation) = self.animation.as_mut() {
+            let start = *animation.start.get_or_insert(time);
+            let elapsed = time.wrapping_sub(start) as f64;
+            let progress = elapsed / self.animation_duration.as_millis() as f64;
+            if progress >= 1.0 {
+                self.active_region = animation.to;
+                self.animation = None;
+            } else {
+                let t = self.animation_easing.apply(progress);
+                let (from, to) = (animation.from, animation.to);
+                let lerp = |a: f64, b: f64| a + (b - a) * t;
+                self.active_region = cairo::Rectangle {
+                    x: lerp(from.x, to.x),
+                    y: lerp(from.y, to.y),
+                    width: lerp(from.width, to.width),
+                    height: lerp(from.height, to.height),
+                };
+            }
+        }
+    }
+    /// Show `capture`, the output as it was before the overlay covered it,
+    /// magnified next to the active region. None hides the magnifier again.
+    pub fn set_magnifier(&mut self, capture: Option<cairo::ImageSurface>) {
+        self.magnifier = capture;
+    }
+    /// Shows a list of windows to pick from, as pairs of label and
+    /// description, or hides it
+    pub fn set_window_list(&mut self, entries: Option<Vec<(String, String)>>) {
+        if entries != self.window_list {
+            self.window_list = entries;
+            // Rare enough not to bother tracking what the list covers
+            self.invalidate();
+        }
     }
-    pub fn get_active_region(&self) -> cairo::Rectangle {
+    /// Shows which profile's bindings are in use, None for just the base ones
+    pub fn set_profile(&mut self, name: Option<String>) {
+        if name != self.profile {
+            self.profile = name;
+            self.invalidate();
+        }
+    }
+    pub fn is_magnifying(&self) -> bool {
+        self.magnifier.is_some()
+    }
+    /// Whether the displayed region is still on its way to the latest one
+    pub fn is_animating(&self) -> bool {
+        self.animation.is_some()
+    }
+    pub fn get_displayed_region(&self) -> cairo::Rectangle {
         self.active_region
     }
     pub fn get_buf_size(&self) -> u32 {
@@ -162,3 +570,26 @@ impl RenderManager {
         self.temp.as_raw_fd()
     }
 }
+
+fn contains(outer: Damage, inner: Damage) -> bool {
+    outer.0 <= inner.0
+        && outer.1 <= inner.1
+        && outer.0 + outer.2 >= inner.0 + inner.2
+        && outer.1 + outer.3 >= inner.1 + inner.3
+}
+
+// `rects` without those that lie within another, which would only be
+// repainted and damaged twice. Of equal ones the first stays.
+fn without_contained(rects: Vec<Damage>) -> Vec<Damage> {
+    rects
+        .iter()
+        .enumerate()
+        .filter(|&(i, &rect)| {
+            !rects
+                .iter()
+                .enumerate()
+                .any(|(j, &other)| j != i && contains(other, rect) && (other != rect || j < i))
+        })
+        .map(|(_, &rect)| rect)
+        .collect()
+}
@@ -0,0 +1,266 @@
+use std::cell::RefCell;
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+
+use log::*;
+
+use wayland_client::{
+    protocol::{wl_output, wl_shm},
+    EventQueue, Main,
+};
+use wayland_protocols::wlr::unstable::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1,
+};
+
+/// A still image of an output, in the output's buffer pixels.
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub width: i32,
+    pub height: i32,
+    pub stride: i32,
+    // Opaque ARGB in native endianness, like cairo's ARGB32
+    pub data: Vec<u8>,
+}
+
+impl Capture {
+    /// The pixel at `x`, `y` as 0xAARRGGBB
+    pub fn pixel(&self, x: i32, y: i32) -> u32 {
+        let offset = (y * self.stride + x * 4) as usize;
+        u32::from_ne_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+    /// The colour under the center of `region` (normalized to the output), or
+    /// the average colour of all of it
+    pub fn sample(&self, region: cairo::Rectangle, average: bool) -> (u8, u8, u8) {
+        let (width, height) = (self.width as f64, self.height as f64);
+        let to_pixel = |v: f64, size: f64| (v * size).floor().clamp(0.0, size - 1.0) as i32;
+        let (x0, y0, x1, y1) = if average {
+            (
+                to_pixel(region.x, width),
+                to_pixel(region.y, height),
+                // Exclusive, but always at least one pixel
+                to_pixel(region.x, width)
+                    .max(((region.x + region.width) * width).ceil().min(width) as i32 - 1)
+                    + 1,
+                to_pixel(region.y, height)
+                    .max(((region.y + region.height) * height).ceil().min(height) as i32 - 1)
+                    + 1,
+            )
+        } else {
+            let x = to_pixel(region.x + region.width / 2.0, width);
+            let y = to_pixel(region.y + region.height / 2.0, height);
+            (x, y, x + 1, y + 1)
+        };
+        let mut sums = [0u64; 3];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let pixel = self.pixel(x, y);
+                sums[0] += (pixel >> 16 & 0xff) as u64;
+                sums[1] += (pixel >> 8 & 0xff) as u64;
+                sums[2] += (pixel & 0xff) as u64;
+            }
+        }
+        let count = ((x1 - x0) * (y1 - y0)) as u64;
+        let channel = |sum: u64| ((sum + count / 2) / count) as u8;
+        (channel(sums[0]), channel(sums[1]), channel(sums[2]))
+    }
+    pub fn to_surface(&self) -> Result<cairo::ImageSurface, String> {
+        cairo::ImageSurface::create_for_data(
+            self.data.clone(),
+            cairo::Format::ARgb32,
+            self.width,
+            self.height,
+            self.stride,
+        )
+        .map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Default)]
+struct FrameState {
+    // format, width, height, stride
+    buffer: Option<(wl_shm::Format, u32, u32, u32)>,
+    y_invert: bool,
+    result: Option<Result<(), String>>,
+}
+
+// Where the red and blue channels of a pixel are, counting bytes of its
+// little endian representation, or None if we can't read the format
+fn channel_offsets(format: wl_shm::Format) -> Option<(usize, usize)> {
+    match format {
+        wl_shm::Format::Argb8888 | wl_shm::Format::Xrgb8888 => Some((2, 0)),
+        wl_shm::Format::Abgr8888 | wl_shm::Format::Xbgr8888 => Some((0, 2)),
+        _ => None,
+    }
+}
+
+/// Copies the current contents of `output`. Blocks, dispatching
+/// `event_queue`, until the compositor is done.
+pub fn capture_output(
+    manager: &Main<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1>,
+    shm: &Main<wl_shm::WlShm>,
+    output: &wl_output::WlOutput,
+    event_queue: &mut EventQueue,
+) -> Result<Capture, String> {
+    let state = Rc::new(RefCell::new(FrameState::default()));
+    let frame = manager.capture_output(0, output);
+    {
+        let state = state.clone();
+        frame.quick_assign(move |_, event, _| {
+            let mut state = state.borrow_mut();
+            match event {
+                zwlr_screencopy_frame_v1::Event::Buffer {
+                    format,
+                    width,
+                    height,
+                    stride,
+                } => state.buffer = Some((format, width, height, stride)),
+                zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                    state.y_invert = flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert)
+                }
+                zwlr_screencopy_frame_v1::Event::Ready { .. } => state.result = Some(Ok(())),
+                zwlr_screencopy_frame_v1::Event::Failed => {
+                    state.result = Some(Err("Compositor failed to copy the output".into()))
+                }
+                _ => (),
+            }
+        });
+    }
+
+    // The buffer parameters are sent right away
+    event_queue
+        .sync_roundtrip(&mut (), |_, _, _| {})
+        .map_err(|err| err.to_string())?;
+    let (format, width, height, stride) = match state.borrow().buffer {
+        Some(buffer) => buffer,
+        None => {
+            frame.destroy();
+            return Err("Compositor offered no shm buffer for screencopy".into());
+        }
+    };
+    let (red, blue) = match channel_offsets(format) {
+        Some(offsets) => offsets,
+        None => {
+            frame.destroy();
+            return Err(format!("Unsupported screencopy format {:?}", format));
+        }
+    };
+    trace!(
+        "Capturing {}x{} output as {:?} (stride {})",
+        width,
+        height,
+        format,
+        stride
+    );
+
+    let size = (stride * height) as usize;
+    let file = tempfile::tempfile().map_err(|err| err.to_string())?;
+    file.set_len(size as u64).map_err(|err| err.to_string())?;
+    let pool = shm.create_pool(file.as_raw_fd(), size as i32);
+    let buffer = pool.create_buffer(0, width as i32, height as i32, stride as i32, format);
+    frame.copy(&buffer);
+
+    while state.borrow().result.is_none() {
+        event_queue
+            .display()
+            .flush()
+            .map_err(|err| err.to_string())?;
+        event_queue
+            .dispatch(&mut (), |_, _, _| {})
+            .map_err(|err| err.to_string())?;
+    }
+    frame.destroy();
+    buffer.destroy();
+    pool.destroy();
+    let state = state.borrow();
+    state.result.clone().unwrap()?;
+
+    let map = unsafe { memmap::MmapOptions::new().len(size).map(&file) }
+        .map_err(|err| err.to_string())?;
+    let out_stride = width as usize * 4;
+    let mut data = vec![0; out_stride * height as usize];
+    for y in 0..height as usize {
+        let src_y = if state.y_invert {
+            height as usize - 1 - y
+        } else {
+            y
+        };
+        let src = &map[src_y * stride as usize..][..out_stride];
+        let dst = &mut data[y * out_stride..][..out_stride];
+        for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+            let pixel =
+                0xff000000 | (src[red] as u32) << 16 | (src[1] as u32) << 8 | src[blue] as u32;
+            dst.copy_from_slice(&pixel.to_ne_bytes());
+        }
+    }
+    Ok(Capture {
+        width: width as i32,
+        height: height as i32,
+        stride: out_stride as i32,
+        data,
+    })
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    // 4x2 capture with a red left half and a blue right half, except for one
+    // white pixel
+    #[allow(dead_code)]
+    fn capture() -> Capture {
+        let mut data = Vec::new();
+        for y in 0..2 {
+            for x in 0..4 {
+                let pixel: u32 = match (x, y) {
+                    (1, 1) => 0xffffffff,
+                    (0..=1, _) => 0xffff0000,
+                    _ => 0xff0000ff,
+                };
+                data.extend(pixel.to_ne_bytes());
+            }
+        }
+        Capture {
+            width: 4,
+            height: 2,
+            stride: 16,
+            data,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn rect(x: f64, y: f64, width: f64, height: f64) -> cairo::Rectangle {
+        cairo::Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn sample_center() {
+        let capture = capture();
+        assert_eq!((0, 0, 255), capture.sample(rect(0.0, 0.0, 1.0, 1.0), false));
+        assert_eq!(
+            (255, 255, 255),
+            capture.sample(rect(0.25, 0.5, 0.25, 0.5), false)
+        );
+        // Clamped to the capture
+        assert_eq!((0, 0, 255), capture.sample(rect(0.5, 0.5, 1.0, 1.0), false));
+    }
+
+    #[test]
+    fn sample_average() {
+        let capture = capture();
+        assert_eq!(
+            (255, 64, 64),
+            capture.sample(rect(0.0, 0.0, 0.5, 1.0), true)
+        );
+        assert_eq!((0, 0, 255), capture.sample(rect(0.5, 0.0, 0.5, 1.0), true));
+        // Tiny regions still cover a pixel
+        assert_eq!(
+            (255, 0, 0),
+            capture.sample(rect(0.01, 0.01, 0.001, 0.001), true)
+        );
+    }
+}
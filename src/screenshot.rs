@@ -0,0 +1,119 @@
+use std::env;
+use std::ffi::CString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::screencopy::Capture;
+
+/// Expands a leading `~` to $HOME and strftime patterns (eg. `%Y%m%d`) using
+/// the local time at `time` (seconds since the epoch).
+pub fn expand_path(pattern: &str, time: i64) -> Result<PathBuf, String> {
+    let pattern = match pattern.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = env::var("HOME").map_err(|_| "$HOME is not set, can't expand ~")?;
+            format!("{}{}", home, rest)
+        }
+        _ => pattern.to_string(),
+    };
+    if !pattern.contains('%') {
+        return Ok(PathBuf::from(pattern));
+    }
+
+    let c_pattern = CString::new(pattern.clone()).map_err(|err| err.to_string())?;
+    let mut buffer = vec![0u8; 4096];
+    let length = unsafe {
+        let time = time as libc::time_t;
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return Err("Failed to get the local time".into());
+        }
+        libc::strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            c_pattern.as_ptr(),
+            &tm,
+        )
+    };
+    if length == 0 {
+        return Err(format!("Failed to expand '{}'", pattern));
+    }
+    buffer.truncate(length);
+    String::from_utf8(buffer)
+        .map(PathBuf::from)
+        .map_err(|err| err.to_string())
+}
+
+/// The current time for [expand_path]
+pub fn now() -> i64 {
+    unsafe { libc::time(std::ptr::null_mut()) as i64 }
+}
+
+/// Writes the part of `capture` under `region` (normalized to the output) to
+/// `path` as a PNG.
+pub fn save(capture: &Capture, region: cairo::Rectangle, path: &Path) -> Result<(), String> {
+    let (width, height) = (capture.width as f64, capture.height as f64);
+    let x0 = (region.x * width).floor().clamp(0.0, width - 1.0);
+    let y0 = (region.y * height).floor().clamp(0.0, height - 1.0);
+    let x1 = ((region.x + region.width) * width)
+        .ceil()
+        .clamp(x0 + 1.0, width);
+    let y1 = ((region.y + region.height) * height)
+        .ceil()
+        .clamp(y0 + 1.0, height);
+
+    let source = capture.to_surface()?;
+    let cropped =
+        cairo::ImageSurface::create(cairo::Format::Rgb24, (x1 - x0) as i32, (y1 - y0) as i32)
+            .map_err(|err| err.to_string())?;
+    {
+        let context = cairo::Context::new(&cropped).map_err(|err| err.to_string())?;
+        context
+            .set_source_surface(&source, -x0, -y0)
+            .map_err(|err| err.to_string())?;
+        context.paint().map_err(|err| err.to_string())?;
+    }
+    let mut file = File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    cropped
+        .write_to_png(&mut file)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn expand_patterns() {
+        // 2001-09-09 01:46:40 UTC, which is still September everywhere
+        let time = 1_000_000_000;
+        assert_eq!(
+            Ok(PathBuf::from("/tmp/shot-2001-09.png")),
+            expand_path("/tmp/shot-%Y-%m.png", time)
+        );
+        assert_eq!(
+            Ok(PathBuf::from("/tmp/100%.png")),
+            expand_path("/tmp/100%%.png", time)
+        );
+        assert_eq!(
+            Ok(PathBuf::from("relative.png")),
+            expand_path("relative.png", time)
+        );
+    }
+
+    #[test]
+    fn expand_home() {
+        // The tests don't otherwise care about $HOME, and this sets it to
+        // what it already is if it is set
+        let home = env::var("HOME").unwrap_or_else(|_| "/home/keynav".into());
+        env::set_var("HOME", &home);
+        assert_eq!(
+            Ok(PathBuf::from(format!("{}/shot.png", home))),
+            expand_path("~/shot.png", 0)
+        );
+        // Only a leading ~ on its own is the home directory
+        assert_eq!(
+            Ok(PathBuf::from("~user/shot.png")),
+            expand_path("~user/shot.png", 0)
+        );
+    }
+}
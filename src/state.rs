@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// Stands in for the name of outputs that didn't tell us theirs
+const UNNAMED_OUTPUT: &str = "-";
+
+/// Where `file` is kept between runs: in $XDG_STATE_HOME/keynav, or
+/// ~/.local/state/keynav.
+pub fn state_path(file: &str) -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(path) if !path.is_empty() => Path::new(&path).join("keynav"),
+        _ => Path::new(&env::var_os("HOME")?).join(".local/state/keynav"),
+    };
+    Some(dir.join(file))
+}
+
+/// Named regions per output, normalized to it. Used for marks and for where
+/// the region was when we last ended.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Regions {
+    by_output: BTreeMap<String, BTreeMap<String, cairo::Rectangle>>,
+}
+
+impl Regions {
+    /// Reads the regions at `path`, where a missing file means there are none
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("Failed to read {}: {}", path.display(), err)),
+        }
+    }
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        fs::write(path, self.to_string())
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+    }
+    /// Parses lines of output, name, x, y, width and height
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut regions = Self::default();
+        for (line_num, line) in (1..).zip(contents.lines()) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = || format!("Error on line {} of saved regions: '{}'", line_num, line);
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [output, name, x, y, width, height] => {
+                    let number = |s: &str| s.parse::<f64>().map_err(|_| error());
+                    regions.by_output.entry(output.into()).or_default().insert(
+                        name.into(),
+                        cairo::Rectangle {
+                            x: number(x)?,
+                            y: number(y)?,
+                            width: number(width)?,
+                            height: number(height)?,
+                        },
+                    );
+                }
+                _ => return Err(error()),
+            }
+        }
+        Ok(regions)
+    }
+    /// The regions saved for the output called `output`
+    pub fn for_output(&self, output: &str) -> HashMap<String, cairo::Rectangle> {
+        self.by_output
+            .get(Self::key(output))
+            .map(|regions| regions.iter().map(|(k, v)| (k.clone(), *v)).collect())
+            .unwrap_or_default()
+    }
+    pub fn set(&mut self, output: &str, name: &str, region: cairo::Rectangle) {
+        self.by_output
+            .entry(Self::key(output).into())
+            .or_default()
+            .insert(name.into(), region);
+    }
+    fn key(output: &str) -> &str {
+        if output.is_empty() {
+            UNNAMED_OUTPUT
+        } else {
+            output
+        }
+    }
+}
+
+impl std::fmt::Display for Regions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# output name x y width height")?;
+        for (output, regions) in &self.by_output {
+            for (name, region) in regions {
+                writeln!(
+                    f,
+                    "{} {} {} {} {} {}",
+                    output, name, region.x, region.y, region.width, region.height
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn regions_round_trip() {
+        let mut regions = Regions::default();
+        let region = cairo::Rectangle {
+            x: 0.25,
+            y: 0.5,
+            width: 0.125,
+            height: 0.0625,
+        };
+        regions.set("DP-1", "a", region);
+        regions.set("", "b", region);
+        assert_eq!(Some(&region), regions.for_output("DP-1").get("a"));
+        assert!(regions.for_output("HDMI-A-1").is_empty());
+
+        let text = regions.to_string();
+        assert!(text.contains("DP-1 a 0.25 0.5 0.125 0.0625\n"));
+        assert!(text.contains("- b 0.25 0.5 0.125 0.0625\n"));
+        assert_eq!(Ok(regions), Regions::parse(&text));
+        assert!(Regions::parse("DP-1 a 0.25 0.5").is_err());
+        assert!(Regions::parse("DP-1 a 0.25 0.5 half 0.5").is_err());
+    }
+
+    #[test]
+    fn regions_are_saved_and_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keynav/marks");
+        assert_eq!(Ok(Regions::default()), Regions::load(&path));
+        let mut regions = Regions::default();
+        regions.set(
+            "eDP-1",
+            "q",
+            cairo::Rectangle {
+                x: 0.0,
+                y: 0.0,
+                width: 1.0,
+                height: 0.5,
+            },
+        );
+        regions.save(&path).unwrap();
+        assert_eq!(Ok(regions), Regions::load(&path));
+    }
+}
@@ -0,0 +1,143 @@
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use serde_json::Value;
+
+const MAGIC: &[u8] = b"i3-ipc";
+const GET_TREE: u32 = 4;
+// For each of sending the request and reading the reply, so that a sway that
+// hangs doesn't take us with it
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A window as sway's tree describes it, which unlike foreign-toplevel
+/// includes where it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwayWindow {
+    pub app_id: String,
+    pub title: String,
+    /// x, y, width and height in the global space
+    pub rect: (i32, i32, i32, i32),
+    pub focused: bool,
+}
+
+/// Asks the sway instance from $SWAYSOCK for its windows.
+pub fn windows() -> Result<Vec<SwayWindow>, String> {
+    let path = env::var_os("SWAYSOCK").ok_or("$SWAYSOCK is not set, not running sway?")?;
+    get_tree(Path::new(&path), TIMEOUT)
+}
+
+fn get_tree(path: &Path, timeout: Duration) -> Result<Vec<SwayWindow>, String> {
+    let mut stream = UnixStream::connect(path).map_err(|err| err.to_string())?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .map_err(|err| err.to_string())?;
+    let mut message = MAGIC.to_vec();
+    message.extend(0u32.to_ne_bytes());
+    message.extend(GET_TREE.to_ne_bytes());
+    stream.write_all(&message).map_err(|err| err.to_string())?;
+
+    let mut header = [0u8; 14];
+    stream
+        .read_exact(&mut header)
+        .map_err(|err| err.to_string())?;
+    if &header[..6] != MAGIC {
+        return Err("Unexpected reply from sway".into());
+    }
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let mut payload = vec![0; length as usize];
+    stream
+        .read_exact(&mut payload)
+        .map_err(|err| err.to_string())?;
+    parse_tree(&String::from_utf8_lossy(&payload))
+}
+
+/// Collects the windows from the reply to GET_TREE.
+pub fn parse_tree(json: &str) -> Result<Vec<SwayWindow>, String> {
+    let tree: Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    let mut windows = Vec::new();
+    collect(&tree, &mut windows);
+    Ok(windows)
+}
+
+fn collect(node: &Value, windows: &mut Vec<SwayWindow>) {
+    // Only views have a pid, workspaces and splits don't
+    if node.get("pid").is_some() {
+        let rect = &node["rect"];
+        let int = |key: &str| rect[key].as_i64().unwrap_or(0) as i32;
+        windows.push(SwayWindow {
+            // Xwayland windows have a class instead
+            app_id: node["app_id"]
+                .as_str()
+                .or_else(|| node["window_properties"]["class"].as_str())
+                .unwrap_or_default()
+                .to_string(),
+            title: node["name"].as_str().unwrap_or_default().to_string(),
+            rect: (int("x"), int("y"), int("width"), int("height")),
+            focused: node["focused"].as_bool().unwrap_or(false),
+        });
+    }
+    for key in ["nodes", "floating_nodes"] {
+        for child in node[key].as_array().into_iter().flatten() {
+            collect(child, windows);
+        }
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn unresponsive_sway_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sway-ipc.sock");
+        // Connections are queued, but nothing ever reads or answers them
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(get_tree(&path, Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn windows_from_tree() {
+        let tree = r#"{
+            "type": "root", "name": "root", "rect": {"x": 0, "y": 0, "width": 3840, "height": 1080},
+            "nodes": [{
+                "type": "output", "name": "DP-1",
+                "nodes": [{
+                    "type": "workspace", "name": "1",
+                    "nodes": [{
+                        "type": "con", "name": "~/src", "app_id": "foot", "pid": 100,
+                        "focused": true,
+                        "rect": {"x": 1920, "y": 0, "width": 960, "height": 1080}
+                    }],
+                    "floating_nodes": [{
+                        "type": "floating_con", "name": "GIMP", "app_id": null, "pid": 101,
+                        "window_properties": {"class": "Gimp"}, "focused": false,
+                        "rect": {"x": 2000, "y": 100, "width": 400, "height": 300}
+                    }]
+                }]
+            }]
+        }"#;
+        assert_eq!(
+            Ok(vec![
+                SwayWindow {
+                    app_id: "foot".into(),
+                    title: "~/src".into(),
+                    rect: (1920, 0, 960, 1080),
+                    focused: true,
+                },
+                SwayWindow {
+                    app_id: "Gimp".into(),
+                    title: "GIMP".into(),
+                    rect: (2000, 100, 400, 300),
+                    focused: false,
+                },
+            ]),
+            parse_tree(tree)
+        );
+        assert!(parse_tree("{").is_err());
+    }
+}
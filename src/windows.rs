@@ -0,0 +1,246 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use log::*;
+
+use wayland_client::{protocol::wl_seat, Main};
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1, zwlr_foreign_toplevel_manager_v1,
+};
+
+// Picker labels are made of these, easiest to type first
+const LABEL_KEYS: &str = "asdfghjklqwertyuiopzxcvbnm";
+
+/// A window of some other client.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Window {
+    /// Unlike its position in [Toplevels::windows] it stays the same while
+    /// the window is open, and isn't reused. 0 for windows that don't come
+    /// from [Toplevels].
+    pub id: u64,
+    pub title: String,
+    pub app_id: String,
+    /// Whether it has focus
+    pub activated: bool,
+}
+
+struct Toplevel {
+    handle: Main<zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1>,
+    window: Window,
+}
+
+/// Keeps track of the open windows through zwlr_foreign_toplevel_manager_v1.
+pub struct Toplevels {
+    toplevels: Rc<RefCell<Vec<Toplevel>>>,
+}
+
+impl Toplevels {
+    pub fn new(
+        manager: Main<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1>,
+    ) -> Self {
+        let toplevels: Rc<RefCell<Vec<Toplevel>>> = Rc::new(RefCell::new(Vec::new()));
+        {
+            let toplevels = toplevels.clone();
+            let mut last_id = 0;
+            manager.quick_assign(move |_, event, _| {
+                if let zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel: handle } =
+                    event
+                {
+                    let toplevels_for_handle = toplevels.clone();
+                    handle.quick_assign(move |handle, event, _| {
+                        let mut toplevels = toplevels_for_handle.borrow_mut();
+                        let index = match toplevels
+                            .iter()
+                            .position(|toplevel| toplevel.handle.as_ref() == handle.as_ref())
+                        {
+                            Some(index) => index,
+                            None => return,
+                        };
+                        let window = &mut toplevels[index].window;
+                        match event {
+                            zwlr_foreign_toplevel_handle_v1::Event::Title { title } => {
+                                window.title = title
+                            }
+                            zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => {
+                                window.app_id = app_id
+                            }
+                            zwlr_foreign_toplevel_handle_v1::Event::State { state } => {
+                                let activated =
+                                    zwlr_foreign_toplevel_handle_v1::State::Activated as u32;
+                                window.activated = state
+                                    .chunks_exact(4)
+                                    .any(|value| value == activated.to_ne_bytes());
+                            }
+                            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                                trace!("Window {:?} closed", window.title);
+                                toplevels.remove(index).handle.destroy();
+                            }
+                            _ => (),
+                        }
+                    });
+                    last_id += 1;
+                    toplevels.borrow_mut().push(Toplevel {
+                        handle,
+                        window: Window {
+                            id: last_id,
+                            ..Window::default()
+                        },
+                    });
+                }
+            });
+        }
+        Toplevels { toplevels }
+    }
+
+    /// The open windows, oldest first
+    pub fn windows(&self) -> Vec<Window> {
+        self.toplevels
+            .borrow()
+            .iter()
+            .map(|toplevel| toplevel.window.clone())
+            .collect()
+    }
+
+    /// Asks the compositor to focus the window with [Window::id] `id`
+    pub fn activate(&self, id: u64, seat: &wl_seat::WlSeat) {
+        let toplevels = self.toplevels.borrow();
+        match toplevels.iter().find(|toplevel| toplevel.window.id == id) {
+            Some(toplevel) => {
+                debug!("Activating {:?}", toplevel.window.title);
+                toplevel.handle.activate(seat);
+            }
+            None => debug!("Window {} was closed before it could be activated", id),
+        }
+    }
+}
+
+/// The part of `rect` (x, y, width and height in the global space) that is on
+/// `output`, normalized to it like the navigator's regions.
+pub fn region_on_output(
+    rect: (i32, i32, i32, i32),
+    output: (i32, i32, i32, i32),
+) -> Option<cairo::Rectangle> {
+    let (x, y, width, height) = rect;
+    let (output_x, output_y, output_width, output_height) = output;
+    let left = x.max(output_x);
+    let top = y.max(output_y);
+    let right = (x + width).min(output_x + output_width);
+    let bottom = (y + height).min(output_y + output_height);
+    if right <= left || bottom <= top {
+        return None;
+    }
+    Some(cairo::Rectangle {
+        x: (left - output_x) as f64 / output_width as f64,
+        y: (top - output_y) as f64 / output_height as f64,
+        width: (right - left) as f64 / output_width as f64,
+        height: (bottom - top) as f64 / output_height as f64,
+    })
+}
+
+/// The labels for `count` entries: single keys while they last, then pairs.
+pub fn labels(count: usize) -> Vec<String> {
+    let keys: Vec<char> = LABEL_KEYS.chars().collect();
+    if count <= keys.len() {
+        keys.iter().take(count).map(|key| key.to_string()).collect()
+    } else {
+        keys.iter()
+            .flat_map(|first| {
+                keys.iter()
+                    .map(move |second| format!("{}{}", first, second))
+            })
+            .take(count)
+            .collect()
+    }
+}
+
+/// What typing a key into a [Picker] did
+#[derive(Debug, PartialEq)]
+pub enum Typed<'a, T> {
+    /// A whole label was typed
+    Chosen(&'a T),
+    /// Some labels start with what was typed so far
+    Partial,
+    /// No label starts with it, so it was ignored
+    NoMatch,
+}
+
+/// Lets the user choose one of some entries by typing its label.
+#[derive(Debug)]
+pub struct Picker<T> {
+    entries: Vec<(String, T)>,
+    typed: String,
+}
+
+impl<T> Picker<T> {
+    pub fn new(items: Vec<T>) -> Self {
+        Picker {
+            entries: labels(items.len()).into_iter().zip(items).collect(),
+            typed: String::new(),
+        }
+    }
+    /// The entries whose label starts with what was typed so far
+    pub fn candidates(&self) -> impl Iterator<Item = &(String, T)> {
+        self.entries
+            .iter()
+            .filter(|(label, _)| label.starts_with(&self.typed))
+    }
+    pub fn type_char(&mut self, c: char) -> Typed<'_, T> {
+        self.typed.push(c);
+        if let Some((_, item)) = self.entries.iter().find(|(label, _)| *label == self.typed) {
+            Typed::Chosen(item)
+        } else if self.candidates().next().is_some() {
+            Typed::Partial
+        } else {
+            self.typed.pop();
+            Typed::NoMatch
+        }
+    }
+}
+
+mod test {
+    #[allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn labels_get_longer_when_keys_run_out() {
+        assert_eq!(vec!["a", "s", "d"], labels(3));
+        let many = labels(30);
+        assert_eq!(30, many.len());
+        assert_eq!(("aa", "as"), (many[0].as_str(), many[1].as_str()));
+        assert_eq!("sd", many[28]);
+    }
+
+    #[test]
+    fn picker_chooses_typed_label() {
+        let mut picker = Picker::new(vec!["firefox", "foot", "emacs"]);
+        assert_eq!(3, picker.candidates().count());
+        assert_eq!(Typed::Chosen(&"foot"), picker.type_char('s'));
+
+        let mut picker = Picker::new((0..30).collect());
+        assert_eq!(Typed::Partial, picker.type_char('s'));
+        assert_eq!(
+            vec![26, 27, 28, 29],
+            picker.candidates().map(|(_, i)| *i).collect::<Vec<_>>()
+        );
+        assert_eq!(Typed::Chosen(&28), picker.type_char('d'));
+
+        let mut picker = Picker::new(vec!["firefox"]);
+        assert_eq!(Typed::NoMatch, picker.type_char('x'));
+        assert_eq!(Typed::Chosen(&"firefox"), picker.type_char('a'));
+    }
+
+    #[test]
+    fn region_is_clipped_to_output() {
+        let output = (1920, 0, 1920, 1080);
+        assert_eq!(
+            Some(cairo::Rectangle {
+                x: 0.5,
+                y: 0.0,
+                width: 0.5,
+                height: 0.5
+            }),
+            region_on_output((2880, -100, 1920, 640), output)
+        );
+        assert_eq!(None, region_on_output((0, 0, 1920, 1080), output));
+    }
+}
@@ -0,0 +1,899 @@
+mod common;
+
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use wayland_server::protocol::wl_pointer::ButtonState;
+
+use keynav_wayland::config::{
+    default_config, parse_actions, Easing, InitialRegion, Length, OutputSection,
+    PointerBackendChoice, Profile, SurfaceChoice, WindowMatch,
+};
+use keynav_wayland::render::RenderManager;
+
+use common::*;
+
+// Premultiplied ARGB of the translucent white fill and of untouched pixels
+const FILL: u32 = 0x33333333;
+const CLEAR: u32 = 0x00000000;
+
+const BTN_LEFT: u32 = 0x110;
+
+#[allow(dead_code)]
+fn warp_to(x: u32, y: u32) -> Vec<PointerRequest> {
+    vec![
+        PointerRequest::MotionAbsolute {
+            x,
+            y,
+            x_extent: 10000,
+            y_extent: 10000,
+        },
+        PointerRequest::Frame,
+    ]
+}
+
+#[allow(dead_code)]
+fn click_at(x: u32, y: u32) -> Vec<PointerRequest> {
+    let mut requests = warp_to(x, y);
+    requests.extend([
+        PointerRequest::Button {
+            button: BTN_LEFT,
+            state: ButtonState::Pressed,
+        },
+        PointerRequest::Frame,
+        PointerRequest::Button {
+            button: BTN_LEFT,
+            state: ButtonState::Released,
+        },
+        PointerRequest::Frame,
+    ]);
+    requests
+}
+
+#[test]
+fn initial_frame_covers_output() {
+    let (compositor, _app) = start(default_config());
+    let frame = compositor.last_frame();
+    assert_eq!((WIDTH, HEIGHT), (frame.width, frame.height));
+    assert_eq!(FILL, frame.pixel(50, 25));
+    assert_eq!(FILL, frame.pixel(150, 75));
+}
+
+#[test]
+fn output_section_overrides_settings() {
+    let mut config = default_config();
+    config.outputs.push(OutputSection {
+        output: "HDMI-A-1".into(),
+        settings: vec![("fill-color".into(), "#00ff00".into())],
+    });
+    // Matches the description
+    config.outputs.push(OutputSection {
+        output: "Mock".into(),
+        settings: vec![
+            ("initial-region".into(), "50% 0 50% 100%".into()),
+            ("fill-color".into(), "#ff000080".into()),
+        ],
+    });
+    let (compositor, _app) = start(config);
+    let frame = compositor.last_frame();
+    assert_eq!(CLEAR, frame.pixel(50, 50));
+    assert_eq!(0x80800000, frame.pixel(125, 25));
+}
+
+#[test]
+fn output_section_follows_the_surface() {
+    let mut config = default_config();
+    config.outputs.push(OutputSection {
+        output: OTHER_OUTPUT_NAME.into(),
+        settings: vec![("fill-color".into(), "#00ff0080".into())],
+    });
+    config.outputs.push(OutputSection {
+        output: OUTPUT_NAME.into(),
+        settings: vec![("fill-color".into(), "#ff000080".into())],
+    });
+    let (compositor, mut app) = start(config);
+    // Before the compositor says where the surface is, only the base settings
+    // apply
+    let first = compositor.with(|state| state.frames[0].clone());
+    assert_eq!(FILL, first.pixel(50, 25));
+    assert_eq!(0x80800000, compositor.last_frame().pixel(50, 25));
+
+    compositor.move_to_output(1);
+    assert!(app.pump());
+    assert_eq!(0x80008000, compositor.last_frame().pixel(50, 25));
+}
+
+#[test]
+fn millimeters_follow_the_output() {
+    let mut config = default_config();
+    config.settings.initial_region = InitialRegion::Fixed {
+        x: Length::Fraction(0.0),
+        y: Length::Fraction(0.0),
+        width: Length::Millimeters(26.5),
+        height: Length::Fraction(1.0),
+    };
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("region 0 0 26.5mm 100%").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    // Until the surface is on an output, at 96dpi rather than the density of
+    // whichever output we heard of first
+    let first = compositor.with(|state| state.frames[0].clone());
+    assert_eq!(FILL, first.pixel(75, 25));
+    assert_eq!(CLEAR, first.pixel(125, 25));
+    // Half of the 53mm wide output
+    assert_eq!(FILL, compositor.last_frame().pixel(75, 25));
+    assert_eq!(CLEAR, compositor.last_frame().pixel(125, 25));
+
+    // A quarter of the other one
+    compositor.move_to_output(1);
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert_eq!(FILL, compositor.last_frame().pixel(10, 25));
+    assert_eq!(CLEAR, compositor.last_frame().pixel(75, 25));
+}
+
+#[test]
+fn initial_region_around_cursor() {
+    let mut config = default_config();
+    config.settings.initial_region = InitialRegion::Cursor;
+    config.settings.cursorzoom_size = (Length::Pixels(50.0), Length::Pixels(50.0));
+    let (compositor, mut app) = start(config);
+    // Everything, until the pointer enters and we know where it is
+    assert_eq!(FILL, compositor.last_frame().pixel(170, 80));
+    compositor.pointer_enter(150.0, 50.0);
+    assert!(app.pump());
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(130, 30));
+    assert_eq!(CLEAR, frame.pixel(50, 50));
+    // Once something was done, the pointer coming back doesn't matter
+    assert!(tap(&compositor, &mut app, KEY_H));
+    compositor.pointer_enter(10.0, 10.0);
+    assert!(app.pump());
+    assert_eq!(CLEAR, compositor.last_frame().pixel(10, 10));
+}
+
+// What the overlay looks like with the default settings and the region at
+// `x`, `y`, `width`, `height` of the output
+fn rendered(x: f64, y: f64, width: f64, height: f64) -> Vec<u8> {
+    let mut renderer =
+        RenderManager::init(cairo::Format::ARgb32, WIDTH as u32, HEIGHT as u32).unwrap();
+    renderer.update_active_region(cairo::Rectangle {
+        x,
+        y,
+        width,
+        height,
+    });
+    renderer.redraw().unwrap();
+    let mut png = Vec::new();
+    renderer.write_png(&mut png).unwrap();
+    let mut surface = cairo::ImageSurface::create_from_png(&mut png.as_slice()).unwrap();
+    let data = surface.data().unwrap().to_vec();
+    data
+}
+
+#[test]
+fn count_prefix_and_repeat() {
+    let mut config = default_config();
+    config.settings.count_keys = true;
+    config
+        .mappings
+        .push((vec!["semicolon".into()], parse_actions("repeat").unwrap()));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_2));
+    assert!(tap(&compositor, &mut app, KEY_L));
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(160, 30));
+    assert_eq!(CLEAR, frame.pixel(140, 30));
+    // Twice more, as that is how often the last binding ran
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(190, 30));
+    assert_eq!(CLEAR, frame.pixel(160, 30));
+    // Counts past 9 and counts for repeat
+    assert!(tap(&compositor, &mut app, KEY_1));
+    assert!(tap(&compositor, &mut app, KEY_0));
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert!(tap(&compositor, &mut app, KEY_1));
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    // The last sixteenth, cut in half ten times and then once more as the new
+    // count replaced the old one
+    assert_eq!(
+        rendered(0.9375, 0.0, 0.0625 / 2048.0, 1.0),
+        compositor.last_frame().data
+    );
+}
+
+#[test]
+fn count_is_dropped_by_unbound_key() {
+    let mut config = default_config();
+    config.settings.count_keys = true;
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_2));
+    // Modifiers on the way to a binding keep it, other keys don't
+    compositor.press(KEY_LEFTSHIFT);
+    assert!(app.pump());
+    compositor.release(KEY_LEFTSHIFT);
+    assert!(app.pump());
+    assert!(tap(&compositor, &mut app, KEY_A));
+    assert!(tap(&compositor, &mut app, KEY_L));
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(110, 30));
+    assert_eq!(CLEAR, frame.pixel(90, 30));
+}
+
+#[test]
+fn count_and_bindings_ignore_lock_modifiers() {
+    let mut config = default_config();
+    config.settings.count_keys = true;
+    let (compositor, mut app) = start(config);
+    compositor.lock_modifiers(MOD_CAPS_LOCK | MOD_NUM_LOCK);
+    assert!(tap(&compositor, &mut app, KEY_2));
+    assert!(tap(&compositor, &mut app, KEY_L));
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(160, 30));
+    assert_eq!(CLEAR, frame.pixel(140, 30));
+}
+
+#[test]
+fn digits_are_bindings_without_count_keys() {
+    let mut config = default_config();
+    config
+        .mappings
+        .push((vec!["2".into()], parse_actions("cut-left").unwrap()));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_2));
+    assert!(tap(&compositor, &mut app, KEY_L));
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(60, 30));
+    assert_eq!(CLEAR, frame.pixel(40, 30));
+}
+
+#[test]
+fn falls_back_to_fullscreen_toplevel() {
+    let (compositor, mut app) = start_without(default_config(), &["zwlr_layer_shell_v1"]);
+    assert!(compositor.with(|state| state.fullscreen));
+    let frame = compositor.last_frame();
+    assert_eq!((WIDTH, HEIGHT), (frame.width, frame.height));
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert_eq!(CLEAR, compositor.last_frame().pixel(150, 75));
+}
+
+#[test]
+fn toplevel_can_be_forced() {
+    let mut config = default_config();
+    config.settings.surface = SurfaceChoice::XdgShell;
+    let (compositor, _app) = start(config);
+    assert!(compositor.with(|state| state.fullscreen));
+    // Nothing stops the pointer from clicking on a fullscreen window before it
+    // entered it, so it lets clicks through from the start
+    assert!(compositor.with(|state| state.input_region_empty_before_configure));
+}
+
+#[test]
+fn missing_protocols_are_reported_together() {
+    match try_start_without(
+        default_config(),
+        &["wl_seat", "zwlr_layer_shell_v1", "xdg_wm_base"],
+    )
+    .1
+    {
+        Ok(_) => panic!("app started without a seat or shell"),
+        Err(err) => assert!(
+            err.ends_with("wl_seat, zwlr_layer_shell_v1 (version 4) or xdg_wm_base"),
+            "{}",
+            err
+        ),
+    }
+}
+
+#[test]
+fn cut_left_redraws_left_half() {
+    let (compositor, mut app) = start(default_config());
+    assert!(tap(&compositor, &mut app, KEY_H));
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(25, 25));
+    assert_eq!(CLEAR, frame.pixel(150, 25));
+    assert_eq!(vec![(0, 0, WIDTH, HEIGHT)], frame.damage);
+    assert_eq!(Vec::<PointerRequest>::new(), compositor.pointer_requests());
+}
+
+#[test]
+fn later_cuts_only_damage_the_old_region() {
+    let (compositor, mut app) = start(default_config());
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert!(tap(&compositor, &mut app, KEY_K));
+    let frame = compositor.last_frame();
+    assert_eq!(vec![(0, 0, 101, HEIGHT)], frame.damage);
+    assert_eq!(FILL, frame.pixel(25, 12));
+    assert_eq!(CLEAR, frame.pixel(25, 75));
+}
+
+#[test]
+fn changes_wait_for_frame_callback() {
+    let (compositor, mut app) = start(default_config());
+    compositor.hold_frames(true);
+    assert!(tap(&compositor, &mut app, KEY_H));
+    let frames = compositor.frame_count();
+
+    assert!(tap(&compositor, &mut app, KEY_J));
+    assert!(tap(&compositor, &mut app, KEY_K));
+    assert_eq!(frames, compositor.frame_count());
+
+    compositor.frame_done();
+    assert!(app.pump());
+    assert_eq!(frames + 1, compositor.frame_count());
+    let frame = compositor.last_frame();
+    assert_eq!(vec![(0, 0, 101, HEIGHT)], frame.damage);
+    assert_eq!(FILL, frame.pixel(25, 60));
+    assert_eq!(CLEAR, frame.pixel(25, 40));
+    assert_eq!(CLEAR, frame.pixel(25, 80));
+}
+
+#[test]
+fn cuts_animate_with_frame_callbacks() {
+    let mut config = default_config();
+    config.settings.animation_duration = Duration::from_millis(32);
+    config.settings.animation_easing = Easing::Linear;
+    let (compositor, mut app) = start(config);
+    // The mock compositor shows a frame every 16ms
+    assert!(tap(&compositor, &mut app, KEY_H));
+    let frame = compositor.last_frame();
+    // Halfway there the region is three quarters of the output wide
+    assert_eq!(FILL, frame.pixel(140, 25));
+    assert_eq!(CLEAR, frame.pixel(170, 25));
+
+    assert!(app.pump());
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(25, 25));
+    assert_eq!(CLEAR, frame.pixel(140, 25));
+}
+
+#[test]
+fn cut_down_then_up() {
+    let (compositor, mut app) = start(default_config());
+    assert!(tap(&compositor, &mut app, KEY_J));
+    assert!(tap(&compositor, &mut app, KEY_K));
+    let frame = compositor.last_frame();
+    assert_eq!(FILL, frame.pixel(50, 60));
+    assert_eq!(CLEAR, frame.pixel(50, 30));
+    assert_eq!(CLEAR, frame.pixel(50, 90));
+}
+
+#[test]
+fn return_clicks_center_and_ends() {
+    let (compositor, mut app) = start(default_config());
+    assert!(!tap(&compositor, &mut app, KEY_ENTER));
+    assert_eq!(
+        [warp_to(5000, 5000), click_at(5000, 5000)].concat(),
+        compositor.pointer_requests()
+    );
+}
+
+#[test]
+fn hold_waits_between_press_and_release() {
+    let mut config = default_config();
+    config
+        .mappings
+        .push((vec!["a".into()], parse_actions("hold 1 100").unwrap()));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_A));
+    let mut requests = warp_to(5000, 5000);
+    requests.extend([
+        PointerRequest::Button {
+            button: BTN_LEFT,
+            state: ButtonState::Pressed,
+        },
+        PointerRequest::Frame,
+    ]);
+    // Events are still dispatched while the button is held, but keys are
+    // ignored until it is released
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert_eq!(requests, compositor.pointer_requests());
+    while compositor.pointer_requests().len() == requests.len() {
+        assert!(app.pump());
+    }
+    requests.extend([
+        PointerRequest::Button {
+            button: BTN_LEFT,
+            state: ButtonState::Released,
+        },
+        PointerRequest::Frame,
+    ]);
+    assert_eq!(requests, compositor.pointer_requests());
+    assert_eq!(FILL, compositor.last_frame().pixel(150, 25));
+    // Timestamped with the time they were sent, not 0
+    match compositor.pointer_times()[..] {
+        [warp, press, release] => {
+            assert!(warp > 0);
+            assert!(press >= warp);
+            assert!(release >= press + 100);
+        }
+        ref times => panic!("Expected three timestamps, got {:?}", times),
+    }
+}
+
+#[test]
+fn keys_are_typed_once_the_overlay_is_gone() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["a".into()],
+        parse_actions("warp, type hi, key ctrl+Return, type h").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    compositor.keep_keyboard_focus(true);
+    assert!(tap(&compositor, &mut app, KEY_A));
+    assert!(!compositor.mapped());
+    assert_eq!(warp_to(5000, 5000), compositor.pointer_requests());
+    // Only once the overlay gave up keyboard focus, or they would come back
+    // to us
+    assert_eq!(
+        Vec::<KeyboardRequest>::new(),
+        compositor.keyboard_requests()
+    );
+    compositor.leave_keyboard();
+    // Ends, as the keys are meant for the window under the overlay
+    assert!(!app.pump());
+    let requests = compositor.keyboard_requests();
+    assert_eq!(KeyboardRequest::Leave, requests[0]);
+    match &requests[1] {
+        KeyboardRequest::Keymap {
+            keymap,
+            overlay_mapped,
+        } => {
+            assert!(!overlay_mapped);
+            assert!(keymap.contains("key <K1> { [ h ] };"));
+            assert!(keymap.contains("key <K2> { [ i ] };"));
+            assert!(keymap.contains("key <K3> { [ Return ] };"));
+        }
+        request => panic!("Expected a keymap first, got {:?}", request),
+    }
+    let press = |key| {
+        [
+            KeyboardRequest::Key { key, state: 1 },
+            KeyboardRequest::Key { key, state: 0 },
+        ]
+    };
+    assert_eq!(
+        [
+            &press(1)[..],
+            &press(2),
+            &[KeyboardRequest::Modifiers { depressed: 4 }],
+            &press(3),
+            &[KeyboardRequest::Modifiers { depressed: 0 }],
+            &press(1),
+        ]
+        .concat(),
+        requests[2..]
+    );
+}
+
+#[test]
+fn keys_are_typed_before_ending() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["a".into()],
+        parse_actions("warp, click 1, key ctrl+a, type h, end").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    compositor.keep_keyboard_focus(true);
+    // Still around to type, even though the binding ended
+    assert!(tap(&compositor, &mut app, KEY_A));
+    assert_eq!(
+        [warp_to(5000, 5000), click_at(5000, 5000)].concat(),
+        compositor.pointer_requests()
+    );
+    compositor.leave_keyboard();
+    assert!(!app.pump());
+    assert_eq!(
+        [
+            KeyboardRequest::Modifiers { depressed: 4 },
+            KeyboardRequest::Key { key: 1, state: 1 },
+            KeyboardRequest::Key { key: 1, state: 0 },
+            KeyboardRequest::Modifiers { depressed: 0 },
+            KeyboardRequest::Key { key: 2, state: 1 },
+            KeyboardRequest::Key { key: 2, state: 0 },
+        ],
+        compositor.keyboard_requests()[2..]
+    );
+}
+
+#[test]
+fn keys_are_typed_even_if_keyboard_focus_stays() {
+    let mut config = default_config();
+    config
+        .mappings
+        .push((vec!["a".into()], parse_actions("type h").unwrap()));
+    let (compositor, mut app) = start(config);
+    compositor.keep_keyboard_focus(true);
+    assert!(tap(&compositor, &mut app, KEY_A));
+    assert!(!compositor.mapped());
+    assert_eq!(
+        Vec::<KeyboardRequest>::new(),
+        compositor.keyboard_requests()
+    );
+    while app.pump() {}
+    let requests = compositor.keyboard_requests();
+    assert!(matches!(requests[0], KeyboardRequest::Keymap { .. }));
+    assert_eq!(
+        [
+            KeyboardRequest::Key { key: 1, state: 1 },
+            KeyboardRequest::Key { key: 1, state: 0 },
+        ],
+        requests[1..]
+    );
+}
+
+#[test]
+fn kde_fake_input_without_virtual_pointer() {
+    let (compositor, mut app) =
+        start_without(default_config(), &["zwlr_virtual_pointer_manager_v1"]);
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert!(!tap(&compositor, &mut app, KEY_ENTER));
+    // In global coordinates, so offset by where the output is
+    let center = PointerRequest::FakeMotionAbsolute {
+        x: (OUTPUT_X + WIDTH / 4) as f64,
+        y: (OUTPUT_Y + HEIGHT / 2) as f64,
+    };
+    assert_eq!(
+        vec![
+            PointerRequest::Authenticate,
+            center.clone(),
+            center,
+            PointerRequest::FakeButton {
+                button: BTN_LEFT,
+                state: 1
+            },
+            PointerRequest::FakeButton {
+                button: BTN_LEFT,
+                state: 0
+            },
+        ],
+        compositor.pointer_requests()
+    );
+}
+
+#[test]
+fn dry_run_leaves_pointer_alone() {
+    let mut config = default_config();
+    config.settings.pointer_backend = PointerBackendChoice::DryRun;
+    let (compositor, mut app) = start(config);
+    assert!(!tap(&compositor, &mut app, KEY_ENTER));
+    assert_eq!(Vec::<PointerRequest>::new(), compositor.pointer_requests());
+}
+
+#[test]
+fn missing_pointer_backend_is_an_error() {
+    let mut config = default_config();
+    config.settings.pointer_backend = PointerBackendChoice::KdeFakeInput;
+    match try_start_without(config, &["org_kde_kwin_fake_input"]).1 {
+        Ok(_) => panic!("app started without its pointer backend"),
+        Err(err) => assert!(err.contains("org_kde_kwin_fake_input"), "{}", err),
+    }
+}
+
+#[test]
+fn shift_moves_region() {
+    let (compositor, mut app) = start(default_config());
+    assert!(tap(&compositor, &mut app, KEY_H));
+    compositor.modifiers(MOD_SHIFT);
+    assert!(tap(&compositor, &mut app, KEY_L));
+    compositor.modifiers(0);
+    assert!(!tap(&compositor, &mut app, KEY_ENTER));
+    assert_eq!(
+        [warp_to(7500, 5000), click_at(7500, 5000)].concat(),
+        compositor.pointer_requests()
+    );
+    assert_eq!(CLEAR, compositor.last_frame().pixel(25, 25));
+}
+
+#[test]
+fn escape_ends_without_touching_pointer() {
+    let (compositor, mut app) = start(default_config());
+    assert!(!tap(&compositor, &mut app, KEY_ESC));
+    assert_eq!(Vec::<PointerRequest>::new(), compositor.pointer_requests());
+}
+
+#[test]
+fn configured_bindings_are_used() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("region 0.5 0.5 50px 50px, warp-to top-left, drag 1").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    let mut expected = [warp_to(5000, 5000), warp_to(6250, 7500)].concat();
+    expected.extend([
+        PointerRequest::Button {
+            button: BTN_LEFT,
+            state: ButtonState::Pressed,
+        },
+        PointerRequest::Frame,
+    ]);
+    assert_eq!(expected, compositor.pointer_requests());
+}
+
+#[test]
+fn screen_is_only_captured_when_needed() {
+    let (compositor, _app) = start(default_config());
+    assert_eq!(
+        Vec::<usize>::new(),
+        compositor.with(|state| state.captures.clone())
+    );
+}
+
+#[test]
+fn magnify_shows_screen_from_before_overlay() {
+    let mut config = default_config();
+    config
+        .mappings
+        .push((vec!["semicolon".into()], parse_actions("magnify").unwrap()));
+    let (compositor, mut app) = start(config);
+    // Both outputs, captured before the first frame, ie. without the overlay
+    // in it
+    assert_eq!(vec![0, 0], compositor.with(|state| state.captures.clone()));
+
+    // The magnifier goes in the top left corner as the region covers
+    // everything, showing the 41 pixels around the center scaled to 50x50
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert_eq!(screen_pixel(88, 38), compositor.last_frame().pixel(10, 10));
+
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert_eq!(FILL, compositor.last_frame().pixel(10, 10));
+}
+
+#[test]
+fn screen_is_captured_for_profiles() {
+    let mut config = default_config();
+    config.profiles.push(Profile {
+        window: WindowMatch::AppId("firefox".into()),
+        mappings: vec![(vec!["semicolon".into()], parse_actions("magnify").unwrap())],
+    });
+    let (compositor, mut app) = start(config);
+    assert_eq!(vec![0, 0], compositor.with(|state| state.captures.clone()));
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert_eq!(screen_pixel(88, 38), compositor.last_frame().pixel(10, 10));
+}
+
+#[test]
+fn pick_color_prints() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("pick-color rgb, end").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    let mut stdout = tempfile::tempfile().unwrap();
+    app.print_to(Box::new(stdout.try_clone().unwrap()));
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert!(!tap(&compositor, &mut app, KEY_SEMICOLON));
+    // The center of the left half
+    let mut printed = String::new();
+    stdout.seek(SeekFrom::Start(0)).unwrap();
+    stdout.read_to_string(&mut printed).unwrap();
+    assert_eq!("rgb(50, 50, 128)\n", printed);
+}
+
+#[test]
+fn print_gives_global_positions() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("print geometry, end").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    let mut stdout = tempfile::tempfile().unwrap();
+    app.print_to(Box::new(stdout.try_clone().unwrap()));
+    assert!(tap(&compositor, &mut app, KEY_L));
+    assert!(tap(&compositor, &mut app, KEY_J));
+    assert!(!tap(&compositor, &mut app, KEY_SEMICOLON));
+    let mut printed = String::new();
+    stdout.seek(SeekFrom::Start(0)).unwrap();
+    stdout.read_to_string(&mut printed).unwrap();
+    assert_eq!(
+        format!(
+            "{},{} {}x{}\n",
+            OUTPUT_X + WIDTH / 2,
+            OUTPUT_Y + HEIGHT / 2,
+            WIDTH / 2,
+            HEIGHT / 2
+        ),
+        printed
+    );
+}
+
+#[test]
+fn pick_color_copies() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("pick-color rgb copy, end").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_H));
+    // Still running to serve the clipboard, but out of the way
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert!(!compositor.mapped());
+    assert!(compositor
+        .selection_mime_types()
+        .contains(&"text/plain;charset=utf-8".to_string()));
+    // The center of the left half
+    assert_eq!(
+        "rgb(50, 50, 128)",
+        compositor.read_selection(&mut app, "text/plain;charset=utf-8")
+    );
+    assert_eq!(
+        "rgb(50, 50, 128)",
+        compositor.read_selection(&mut app, "UTF8_STRING")
+    );
+
+    compositor.cancel_selection();
+    assert!(!app.pump());
+}
+
+#[test]
+fn screenshot_saves_region() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shot.png");
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions(&format!("screenshot {}", path.display())).unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_L));
+    assert!(tap(&compositor, &mut app, KEY_J));
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+
+    let mut shot =
+        cairo::ImageSurface::create_from_png(&mut std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!((100, 50), (shot.width(), shot.height()));
+    let stride = shot.stride() as usize;
+    let data = shot.data().unwrap();
+    let pixel = |x: usize, y: usize| {
+        u32::from_ne_bytes(data[y * stride + x * 4..][..4].try_into().unwrap()) | 0xff000000
+    };
+    // No trace of the overlay, just what was on screen
+    assert_eq!(screen_pixel(100, 50), pixel(0, 0));
+    assert_eq!(screen_pixel(199, 99), pixel(99, 49));
+}
+
+#[test]
+fn copy_describes_region() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("copy %x %y %wx%h on %o, end").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    // Data control is preferred as it doesn't depend on focus
+    assert!(compositor.selection_is_data_control());
+    assert_eq!(
+        format!("{} {} 100x100 on {}", OUTPUT_X, OUTPUT_Y, OUTPUT_NAME),
+        compositor.read_selection(&mut app, "text/plain")
+    );
+
+    compositor.cancel_selection();
+    assert!(!app.pump());
+}
+
+#[test]
+fn profile_of_focused_window_is_layered_on_top() {
+    let mut config = default_config();
+    config.profiles.push(Profile {
+        window: WindowMatch::AppId("foot".into()),
+        mappings: vec![(vec!["h".into()], parse_actions("end").unwrap())],
+    });
+    config.profiles.push(Profile {
+        window: WindowMatch::Title("Firefox".into()),
+        mappings: vec![(vec!["semicolon".into()], parse_actions("warp").unwrap())],
+    });
+    // Matches too, but comes second
+    config.profiles.push(Profile {
+        window: WindowMatch::AppId("firefox".into()),
+        mappings: vec![
+            (vec!["semicolon".into()], parse_actions("end").unwrap()),
+            (vec!["l".into()], parse_actions("end").unwrap()),
+        ],
+    });
+    let (compositor, mut app) = start(config);
+    let (plain_compositor, mut plain_app) = start(default_config());
+    assert!(tap(&compositor, &mut app, KEY_J));
+    assert!(tap(&plain_compositor, &mut plain_app, KEY_J));
+    // The profile's name is shown
+    assert_ne!(
+        plain_compositor.last_frame().data,
+        compositor.last_frame().data
+    );
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert_eq!(warp_to(5000, 7500), compositor.pointer_requests());
+    // Only the first matching profile is used, and the base bindings remain
+    assert!(tap(&compositor, &mut app, KEY_L));
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert!(!tap(&compositor, &mut app, KEY_ESC));
+}
+
+#[test]
+fn window_select_activates_labelled_window() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("window-select").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    let before = compositor.last_frame().data;
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert_ne!(before, compositor.last_frame().data);
+    // While the list is up keys pick windows instead of running actions
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert!(compositor.activated().is_empty());
+    assert!(tap(&compositor, &mut app, KEY_S));
+    assert_eq!(vec![1], compositor.activated());
+    assert_eq!(before, compositor.last_frame().data);
+}
+
+#[test]
+fn window_select_follows_windows_that_close() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("window-select").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    compositor.close_window(0);
+    // Still the window that was labelled with s, though it moved up the list
+    assert!(tap(&compositor, &mut app, KEY_S));
+    assert_eq!(vec![1], compositor.activated());
+}
+
+#[test]
+fn window_select_leaves_out_own_window() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("window-select").unwrap(),
+    ));
+    let (compositor, mut app) = start_without(config, &["zwlr_layer_shell_v1"]);
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    // Only the other two windows got labels
+    assert!(tap(&compositor, &mut app, KEY_D));
+    assert!(compositor.activated().is_empty());
+    assert!(tap(&compositor, &mut app, KEY_S));
+    assert_eq!(vec![1], compositor.activated());
+}
+
+#[test]
+fn window_select_can_be_cancelled() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("window-select").unwrap(),
+    ));
+    let (compositor, mut app) = start(config);
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert!(tap(&compositor, &mut app, KEY_ESC));
+    assert!(compositor.activated().is_empty());
+    // Escape only closed the list, so the next one ends as usual
+    assert!(!tap(&compositor, &mut app, KEY_ESC));
+}
+
+#[test]
+fn copy_falls_back_to_data_device() {
+    let mut config = default_config();
+    config.mappings.push((
+        vec!["semicolon".into()],
+        parse_actions("copy center, end").unwrap(),
+    ));
+    let (compositor, mut app) = start_without(config, &["zwlr_data_control_manager_v1"]);
+    assert!(tap(&compositor, &mut app, KEY_H));
+    assert!(tap(&compositor, &mut app, KEY_SEMICOLON));
+    assert!(!compositor.selection_is_data_control());
+    assert_eq!(
+        format!("{},{}", OUTPUT_X + 50, OUTPUT_Y + 50),
+        compositor.read_selection(&mut app, "STRING")
+    );
+}
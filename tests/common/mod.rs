@@ -0,0 +1,1635 @@
+// An in-process compositor that is just capable enough to run keynav-wayland
+// against. It runs on its own thread and records what the app asks of it so
+// that tests can assert on the exact pointer requests and committed buffers.
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use wayland_protocols_server::xdg::shell::server::{xdg_surface, xdg_toplevel, xdg_wm_base};
+use wayland_protocols_wlr::data_control::v1::server::{
+    zwlr_data_control_device_v1, zwlr_data_control_manager_v1, zwlr_data_control_source_v1,
+};
+use wayland_protocols_wlr::foreign_toplevel::v1::server::{
+    zwlr_foreign_toplevel_handle_v1, zwlr_foreign_toplevel_manager_v1,
+};
+use wayland_protocols_wlr::layer_shell::v1::server::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+use wayland_protocols_wlr::screencopy::v1::server::{
+    zwlr_screencopy_frame_v1, zwlr_screencopy_manager_v1,
+};
+use wayland_protocols_wlr::virtual_pointer::v1::server::{
+    zwlr_virtual_pointer_manager_v1, zwlr_virtual_pointer_v1,
+};
+use wayland_server::backend::{ClientData, ClientId, DisconnectReason};
+use wayland_server::protocol::{
+    wl_buffer, wl_callback, wl_compositor, wl_data_device, wl_data_device_manager, wl_data_source,
+    wl_keyboard, wl_output, wl_pointer, wl_region, wl_seat, wl_shm, wl_shm_pool, wl_surface,
+};
+use wayland_server::{
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+};
+
+use keynav_wayland::app::AppRunner;
+
+use fake_input::org_kde_kwin_fake_input;
+use virtual_keyboard::{zwp_virtual_keyboard_manager_v1, zwp_virtual_keyboard_v1};
+
+// Server side of the vendored KDE protocol
+mod fake_input {
+    // The generated code refers to super::wayland_server
+    #[allow(clippy::single_component_path_imports)]
+    use wayland_server;
+
+    pub mod __interfaces {
+        use wayland_server::backend as wayland_backend;
+        wayland_scanner::generate_interfaces!("protocols/fake-input.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("protocols/fake-input.xml");
+}
+
+// Server side of the vendored virtual keyboard protocol
+mod virtual_keyboard {
+    #[allow(clippy::single_component_path_imports)]
+    use wayland_server;
+    use wayland_server::protocol::*;
+
+    pub mod __interfaces {
+        use wayland_server::backend as wayland_backend;
+        use wayland_server::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocols/virtual-keyboard-unstable-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_server_code!("protocols/virtual-keyboard-unstable-v1.xml");
+}
+use keynav_wayland::config::RawConfig;
+
+// Size of the output that the layer surface is configured to
+pub const WIDTH: i32 = 200;
+pub const HEIGHT: i32 = 100;
+// Roughly 96dpi
+pub const PHYSICAL_WIDTH: i32 = 53;
+pub const PHYSICAL_HEIGHT: i32 = 26;
+pub const OUTPUT_NAME: &str = "MOCK-1";
+// Where the output is in the global space
+pub const OUTPUT_X: i32 = 1920;
+pub const OUTPUT_Y: i32 = 40;
+// Another output with as many pixels to its left, but twice as big, which the
+// surface is only on after [MockCompositor::move_to_output]
+pub const OTHER_OUTPUT_NAME: &str = "MOCK-2";
+// As (name, description, x, y, physical width, physical height), indexed like
+// the argument of [MockCompositor::move_to_output]. The surface starts out on
+// the first.
+const OUTPUTS: [(&str, &str, i32, i32, i32, i32); 2] = [
+    (
+        OUTPUT_NAME,
+        "Mock output",
+        OUTPUT_X,
+        OUTPUT_Y,
+        PHYSICAL_WIDTH,
+        PHYSICAL_HEIGHT,
+    ),
+    (
+        OTHER_OUTPUT_NAME,
+        "Spare screen",
+        OUTPUT_X - WIDTH,
+        OUTPUT_Y,
+        2 * PHYSICAL_WIDTH,
+        2 * PHYSICAL_HEIGHT,
+    ),
+];
+// The windows of other clients, as (title, app_id)
+pub const WINDOWS: [(&str, &str); 2] = [("Mozilla Firefox", "firefox"), ("~/src", "foot")];
+// Index into [WINDOWS] of the one with focus
+pub const FOCUSED_WINDOW: usize = 0;
+
+// What is "on screen" at x, y, as 0xAARRGGBB. Distinct for every pixel of the
+// output.
+pub fn screen_pixel(x: i32, y: i32) -> u32 {
+    0xff000080 | (x as u32) << 16 | (y as u32) << 8
+}
+
+// evdev codes (what wl_keyboard.key carries, ie. xkb keycodes - 8)
+pub const KEY_ESC: u32 = 1;
+pub const KEY_1: u32 = 2;
+pub const KEY_2: u32 = 3;
+pub const KEY_0: u32 = 11;
+pub const KEY_A: u32 = 30;
+pub const KEY_S: u32 = 31;
+pub const KEY_D: u32 = 32;
+pub const KEY_ENTER: u32 = 28;
+pub const KEY_H: u32 = 35;
+pub const KEY_J: u32 = 36;
+pub const KEY_K: u32 = 37;
+pub const KEY_L: u32 = 38;
+pub const KEY_SEMICOLON: u32 = 39;
+pub const KEY_LEFTSHIFT: u32 = 42;
+
+// Index of Shift in [KEYMAP], as a mask for wl_keyboard.modifiers
+pub const MOD_SHIFT: u32 = 1;
+// Of CapsLock and NumLock, which are Lock and Mod2 in every keymap
+pub const MOD_CAPS_LOCK: u32 = 2;
+pub const MOD_NUM_LOCK: u32 = 16;
+
+// Just enough of a keymap for the default config, without relying on the xkb
+// data files of whatever machine the tests run on
+const KEYMAP: &str = r#"xkb_keymap {
+xkb_keycodes "test" {
+    minimum = 8;
+    maximum = 255;
+    <ESC> = 9;
+    <AE01> = 10;
+    <AE02> = 11;
+    <AE10> = 19;
+    <RTRN> = 36;
+    <AC01> = 38;
+    <AC02> = 39;
+    <AC03> = 40;
+    <AC06> = 43;
+    <AC07> = 44;
+    <AC08> = 45;
+    <AC09> = 46;
+    <AC10> = 47;
+    <LFSH> = 50;
+};
+xkb_types "test" {
+    type "ONE_LEVEL" {
+        modifiers = none;
+        level_name[Level1] = "Any";
+    };
+    type "TWO_LEVEL" {
+        modifiers = Shift;
+        map[Shift] = Level2;
+        level_name[Level1] = "Base";
+        level_name[Level2] = "Shift";
+    };
+};
+xkb_compatibility "test" {
+    interpret Shift_L {
+        action = SetMods(modifiers = Shift);
+    };
+};
+xkb_symbols "test" {
+    key <ESC> { [ Escape ] };
+    key <AE01> { [ 1 ] };
+    key <AE02> { [ 2 ] };
+    key <AE10> { [ 0 ] };
+    key <RTRN> { [ Return ] };
+    key <AC01> { [ a, A ] };
+    key <AC02> { [ s, S ] };
+    key <AC03> { [ d, D ] };
+    key <AC06> { [ h, H ] };
+    key <AC07> { [ j, J ] };
+    key <AC08> { [ k, K ] };
+    key <AC09> { [ l, L ] };
+    key <AC10> { [ semicolon, colon ] };
+    key <LFSH> { [ Shift_L ] };
+    modifier_map Shift { <LFSH> };
+};
+};
+"#;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PointerRequest {
+    MotionAbsolute {
+        x: u32,
+        y: u32,
+        x_extent: u32,
+        y_extent: u32,
+    },
+    Button {
+        button: u32,
+        state: wl_pointer::ButtonState,
+    },
+    Frame,
+    // org_kde_kwin_fake_input
+    Authenticate,
+    FakeMotionAbsolute {
+        x: f64,
+        y: f64,
+    },
+    FakeButton {
+        button: u32,
+        state: u32,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum KeyboardRequest {
+    // Along with whether the overlay was still mapped when it came
+    Keymap {
+        keymap: String,
+        overlay_mapped: bool,
+    },
+    Key {
+        key: u32,
+        state: u32,
+    },
+    Modifiers {
+        depressed: u32,
+    },
+    // Not a request, but where among them the app's wl_keyboard lost focus
+    Leave,
+}
+
+// A buffer as it was when the surface was committed
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: i32,
+    pub height: i32,
+    pub stride: i32,
+    pub data: Vec<u8>,
+    // (x, y, width, height) in buffer coordinates
+    pub damage: Vec<(i32, i32, i32, i32)>,
+}
+
+impl Frame {
+    // Premultiplied ARGB, like cairo's ARGB32
+    pub fn pixel(&self, x: i32, y: i32) -> u32 {
+        let offset = (y * self.stride + x * 4) as usize;
+        u32::from_ne_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+}
+
+struct Pool {
+    file: File,
+    map: memmap::MmapMut,
+}
+
+impl Pool {
+    fn new(fd: OwnedFd, size: i32) -> Self {
+        let file = unsafe { File::from_raw_fd(fd.into_raw_fd()) };
+        let map = unsafe {
+            memmap::MmapOptions::new()
+                .len(size as usize)
+                .map_mut(&file)
+                .unwrap()
+        };
+        Pool { file, map }
+    }
+}
+
+struct BufferData {
+    pool: Arc<Mutex<Pool>>,
+    offset: i32,
+    width: i32,
+    height: i32,
+    stride: i32,
+}
+
+// A source set as the selection through either of the clipboard protocols
+pub enum Selection {
+    DataDevice(wl_data_source::WlDataSource),
+    DataControl(zwlr_data_control_source_v1::ZwlrDataControlSourceV1),
+}
+
+impl Selection {
+    pub fn mime_types(&self) -> Vec<String> {
+        let mime_types = match self {
+            Selection::DataDevice(source) => source.data::<Mutex<Vec<String>>>(),
+            Selection::DataControl(source) => source.data::<Mutex<Vec<String>>>(),
+        };
+        mime_types.unwrap().lock().unwrap().clone()
+    }
+    fn send(&self, mime_type: String, fd: std::os::unix::io::BorrowedFd) {
+        match self {
+            Selection::DataDevice(source) => source.send(mime_type, fd),
+            Selection::DataControl(source) => source.send(mime_type, fd),
+        }
+    }
+    fn cancelled(&self) {
+        match self {
+            Selection::DataDevice(source) => source.cancelled(),
+            Selection::DataControl(source) => source.cancelled(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct State {
+    pub pointer_requests: Vec<PointerRequest>,
+    // Of the motion and button requests of the virtual pointer
+    pub pointer_times: Vec<u32>,
+    pub keyboard_requests: Vec<KeyboardRequest>,
+    pub frames: Vec<Frame>,
+    keyboards: Vec<wl_keyboard::WlKeyboard>,
+    pointers: Vec<wl_pointer::WlPointer>,
+    surface: Option<wl_surface::WlSurface>,
+    // The wl_outputs the app bound, with the index into [OUTPUTS] of each
+    outputs: Vec<(usize, wl_output::WlOutput)>,
+    // Index into [OUTPUTS] of the one the surface is on, None until it is
+    // first shown
+    entered_output: Option<usize>,
+    layer_surface: Option<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1>,
+    // Instead of [State.layer_surface] when the app falls back to xdg-shell
+    toplevel: Option<(xdg_surface::XdgSurface, xdg_toplevel::XdgToplevel)>,
+    pub fullscreen: bool,
+    configured: bool,
+    // Set by wl_surface.attach, None meaning nothing was attached since the
+    // last commit
+    pending_buffer: Option<Option<wl_buffer::WlBuffer>>,
+    // Whether the surface has a buffer, ie. is shown
+    pub mapped: bool,
+    // Set by wl_surface.set_input_region, Some(true) for a region and
+    // Some(false) for none. The app never adds anything to its regions, so a
+    // region is an empty one.
+    pending_input_region: Option<bool>,
+    // Whether clicks on the surface go to the windows below
+    pub input_region_empty: bool,
+    // [State.input_region_empty] as of the commit the surface got its first
+    // configure in response to
+    pub input_region_empty_before_configure: bool,
+    pub selection: Option<Selection>,
+    pending_damage: Vec<(i32, i32, i32, i32)>,
+    pending_callbacks: Vec<wl_callback::WlCallback>,
+    // Frame callbacks of committed frames that haven't been "shown" yet
+    frame_callbacks: Vec<wl_callback::WlCallback>,
+    // When set frames are only shown on [State::frame_done], otherwise as soon
+    // as they are committed
+    pub hold_frames: bool,
+    // When set the app's wl_keyboard keeps focus after the surface is hidden,
+    // until [State::leave_keyboard]
+    pub keep_keyboard_focus: bool,
+    // For every screencopy, how many frames the app had committed by then
+    pub captures: Vec<usize>,
+    // Indices into [WINDOWS] of the windows the app asked to activate, or
+    // WINDOWS.len() for the app's own xdg-shell window
+    pub activated: Vec<usize>,
+    // Set by xdg_toplevel.set_app_id, as the app's own window is listed
+    // along with [WINDOWS]
+    app_id: Option<String>,
+    // The foreign toplevel handles of [WINDOWS], by index
+    windows: Vec<zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1>,
+    serial: u32,
+    time: u32,
+    // Answers to [MockCompositor::with], sent after flushing
+    replies: Vec<Box<dyn FnOnce() + Send>>,
+}
+
+impl State {
+    fn next_serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+    pub fn key(&mut self, key: u32, state: wl_keyboard::KeyState) {
+        let serial = self.next_serial();
+        for keyboard in &self.keyboards {
+            keyboard.key(serial, 0, key, state);
+        }
+    }
+    // Like a real compositor, keyboard focus comes and goes with the surface
+    // being shown
+    fn set_keyboard_focus(&mut self, focused: bool) {
+        if !focused && self.keep_keyboard_focus {
+            return;
+        }
+        let serial = self.next_serial();
+        let Some(surface) = &self.surface else {
+            return;
+        };
+        for keyboard in &self.keyboards {
+            if focused {
+                keyboard.enter(serial, surface, Vec::new());
+            } else {
+                keyboard.leave(serial, surface);
+            }
+        }
+        if !focused && !self.keyboards.is_empty() {
+            self.keyboard_requests.push(KeyboardRequest::Leave);
+        }
+    }
+    // The `index`th of [WINDOWS] is closed
+    pub fn close_window(&mut self, index: usize) {
+        self.windows[index].closed();
+    }
+    pub fn leave_keyboard(&mut self) {
+        self.keep_keyboard_focus = false;
+        self.set_keyboard_focus(false);
+    }
+    // The pointer moves onto the app's surface, at `x`, `y` in surface pixels
+    pub fn pointer_enter(&mut self, x: f64, y: f64) {
+        let serial = self.next_serial();
+        if let Some(surface) = &self.surface {
+            for pointer in &self.pointers {
+                pointer.enter(serial, surface, x, y);
+            }
+        }
+    }
+    // Sends wl_surface.leave for the output the surface is on, if any, and
+    // wl_surface.enter for the one at `index` in [OUTPUTS]
+    pub fn move_to_output(&mut self, index: usize) {
+        let Some(surface) = &self.surface else {
+            return;
+        };
+        for (output_index, output) in &self.outputs {
+            if Some(*output_index) == self.entered_output {
+                surface.leave(output);
+            }
+        }
+        for (output_index, output) in &self.outputs {
+            if *output_index == index {
+                surface.enter(output);
+            }
+        }
+        self.entered_output = Some(index);
+    }
+    pub fn modifiers(&mut self, depressed: u32) {
+        let serial = self.next_serial();
+        for keyboard in &self.keyboards {
+            keyboard.modifiers(serial, depressed, 0, 0, 0);
+        }
+    }
+    pub fn lock_modifiers(&mut self, locked: u32) {
+        let serial = self.next_serial();
+        for keyboard in &self.keyboards {
+            keyboard.modifiers(serial, 0, 0, locked, 0);
+        }
+    }
+    pub fn frame_done(&mut self) {
+        self.time += 16;
+        for callback in self.frame_callbacks.drain(..) {
+            callback.done(self.time);
+        }
+    }
+    fn set_selection(&mut self, selection: Option<Selection>) {
+        if let Some(old) = std::mem::replace(&mut self.selection, selection) {
+            old.cancelled();
+        }
+    }
+    pub fn last_frame(&self) -> &Frame {
+        self.frames.last().expect("app to have committed a buffer")
+    }
+}
+
+struct TestClient;
+impl ClientData for TestClient {
+    fn initialized(&self, _client_id: ClientId) {}
+    fn disconnected(&self, _client_id: ClientId, _reason: DisconnectReason) {}
+}
+
+fn create_global<I: Resource + 'static>(handle: &DisplayHandle, version: u32, missing: &[&str])
+where
+    State: GlobalDispatch<I, ()>,
+{
+    if !missing.contains(&I::interface().name) {
+        handle.create_global::<State, I, ()>(version, ());
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut State) + Send>;
+
+pub struct MockCompositor {
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MockCompositor {
+    // Starts the compositor and returns it together with the client end of
+    // its only connection. Globals with an interface in [missing] are left
+    // out.
+    pub fn start(missing: &'static [&'static str]) -> (Self, UnixStream) {
+        let (server_stream, client_stream) = UnixStream::pair().unwrap();
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let thread = thread::spawn(move || {
+            let mut display = Display::<State>::new().unwrap();
+            let handle = display.handle();
+            create_global::<wl_compositor::WlCompositor>(&handle, 4, missing);
+            create_global::<wl_shm::WlShm>(&handle, 1, missing);
+            create_global::<wl_seat::WlSeat>(&handle, 7, missing);
+            if !missing.contains(&"wl_output") {
+                // The other one first, so that picking the first output the
+                // app heard of is picking the wrong one
+                for index in (0..OUTPUTS.len()).rev() {
+                    handle.create_global::<State, wl_output::WlOutput, usize>(4, index);
+                }
+            }
+            create_global::<wl_data_device_manager::WlDataDeviceManager>(&handle, 3, missing);
+            create_global::<zwlr_layer_shell_v1::ZwlrLayerShellV1>(&handle, 4, missing);
+            create_global::<xdg_wm_base::XdgWmBase>(&handle, 1, missing);
+            create_global::<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1>(
+                &handle, 2, missing,
+            );
+            create_global::<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1>(
+                &handle, 3, missing,
+            );
+            create_global::<org_kde_kwin_fake_input::OrgKdeKwinFakeInput>(&handle, 4, missing);
+            create_global::<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1>(
+                &handle, 1, missing,
+            );
+            create_global::<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1>(
+                &handle, 3, missing,
+            );
+            create_global::<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1>(
+                &handle, 2, missing,
+            );
+            display
+                .handle()
+                .insert_client(server_stream, Arc::new(TestClient))
+                .unwrap();
+
+            let mut state = State::default();
+            loop {
+                // Always read what the client has sent before running a job,
+                // so jobs see everything the client did before asking
+                match job_receiver.recv_timeout(Duration::from_millis(1)) {
+                    Ok(job) => {
+                        display.dispatch_clients(&mut state).unwrap();
+                        job(&mut state);
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        display.dispatch_clients(&mut state).unwrap();
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+                display.flush_clients().unwrap();
+                for reply in std::mem::take(&mut state.replies) {
+                    reply();
+                }
+            }
+        });
+        (
+            MockCompositor {
+                jobs: Some(jobs),
+                thread: Some(thread),
+            },
+            client_stream,
+        )
+    }
+
+    // Runs [f] on the compositor thread once it has processed everything the
+    // client has flushed so far
+    pub fn with<R: Send + 'static>(&self, f: impl FnOnce(&mut State) -> R + Send + 'static) -> R {
+        let (sender, receiver) = mpsc::channel();
+        self.jobs
+            .as_ref()
+            .unwrap()
+            .send(Box::new(move |state: &mut State| {
+                // Only once what the job sent has been flushed, so that it is
+                // there for the app's next dispatch
+                let result = f(state);
+                state
+                    .replies
+                    .push(Box::new(move || sender.send(result).unwrap()));
+            }))
+            .unwrap();
+        receiver.recv().unwrap()
+    }
+
+    pub fn press(&self, key: u32) {
+        self.with(move |state| state.key(key, wl_keyboard::KeyState::Pressed));
+    }
+    pub fn release(&self, key: u32) {
+        self.with(move |state| state.key(key, wl_keyboard::KeyState::Released));
+    }
+    pub fn modifiers(&self, depressed: u32) {
+        self.with(move |state| state.modifiers(depressed));
+    }
+    pub fn lock_modifiers(&self, locked: u32) {
+        self.with(move |state| state.lock_modifiers(locked));
+    }
+    pub fn pointer_enter(&self, x: f64, y: f64) {
+        self.with(move |state| state.pointer_enter(x, y));
+    }
+    pub fn move_to_output(&self, index: usize) {
+        self.with(move |state| state.move_to_output(index));
+    }
+    pub fn pointer_requests(&self) -> Vec<PointerRequest> {
+        self.with(|state| state.pointer_requests.clone())
+    }
+    pub fn pointer_times(&self) -> Vec<u32> {
+        self.with(|state| state.pointer_times.clone())
+    }
+    pub fn keyboard_requests(&self) -> Vec<KeyboardRequest> {
+        self.with(|state| state.keyboard_requests.clone())
+    }
+    pub fn last_frame(&self) -> Frame {
+        self.with(|state| state.last_frame().clone())
+    }
+    pub fn frame_count(&self) -> usize {
+        self.with(|state| state.frames.len())
+    }
+    pub fn hold_frames(&self, hold: bool) {
+        self.with(move |state| state.hold_frames = hold);
+    }
+    pub fn frame_done(&self) {
+        self.with(|state| state.frame_done());
+    }
+    pub fn keep_keyboard_focus(&self, keep: bool) {
+        self.with(move |state| state.keep_keyboard_focus = keep);
+    }
+    pub fn leave_keyboard(&self) {
+        self.with(|state| state.leave_keyboard());
+    }
+    pub fn mapped(&self) -> bool {
+        self.with(|state| state.mapped)
+    }
+    pub fn selection_mime_types(&self) -> Vec<String> {
+        self.with(|state| {
+            state
+                .selection
+                .as_ref()
+                .map(Selection::mime_types)
+                .unwrap_or_default()
+        })
+    }
+    // Whether the selection was set through data control rather than
+    // wl_data_device
+    pub fn activated(&self) -> Vec<usize> {
+        self.with(|state| state.activated.clone())
+    }
+    pub fn close_window(&self, index: usize) {
+        self.with(move |state| state.close_window(index));
+    }
+    pub fn selection_is_data_control(&self) -> bool {
+        self.with(|state| matches!(state.selection, Some(Selection::DataControl(_))))
+    }
+    // Asks the app for the selection, letting it answer with [app.pump]
+    pub fn read_selection(&self, app: &mut AppRunner, mime_type: &str) -> String {
+        let mime_type = mime_type.to_string();
+        let mut reader = self.with(move |state| {
+            let (reader, writer) = UnixStream::pair().unwrap();
+            state
+                .selection
+                .as_ref()
+                .expect("app to have set a selection")
+                .send(mime_type, writer.as_fd());
+            reader
+        });
+        app.pump();
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).unwrap();
+        contents
+    }
+    // Another client takes over the clipboard
+    pub fn cancel_selection(&self) {
+        self.with(|state| {
+            if let Some(source) = state.selection.take() {
+                source.cancelled();
+            }
+        });
+    }
+}
+
+impl Drop for MockCompositor {
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            // Don't hide the original panic behind a second one
+            if !thread::panicking() {
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+// Starts a compositor and connects keynav-wayland to it
+pub fn start(config: RawConfig) -> (MockCompositor, AppRunner) {
+    start_without(config, &[])
+}
+
+// Like [start], but the compositor lacks the globals in [missing]
+pub fn start_without(
+    config: RawConfig,
+    missing: &'static [&'static str],
+) -> (MockCompositor, AppRunner) {
+    let (compositor, app) = try_start_without(config, missing);
+    (compositor, app.unwrap())
+}
+
+// Like [start_without], for when the app is expected to refuse to start
+pub fn try_start_without(
+    config: RawConfig,
+    missing: &'static [&'static str],
+) -> (MockCompositor, Result<AppRunner, String>) {
+    let (compositor, stream) = MockCompositor::start(missing);
+    let display = unsafe { wayland_client::Display::from_fd(stream.into_raw_fd()) }.unwrap();
+    let app = AppRunner::init_with_display(display, config);
+    (compositor, app)
+}
+
+// Presses and releases [key], letting the app handle each event. Returns
+// whether the app is still running
+pub fn tap(compositor: &MockCompositor, app: &mut AppRunner, key: u32) -> bool {
+    compositor.press(key);
+    if !app.pump() {
+        return false;
+    }
+    compositor.release(key);
+    app.pump()
+}
+
+impl GlobalDispatch<wl_compositor::WlCompositor, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<wl_compositor::WlCompositor>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<wl_compositor::WlCompositor, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &wl_compositor::WlCompositor,
+        request: wl_compositor::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_compositor::Request::CreateSurface { id } => {
+                state.surface = Some(data_init.init(id, ()));
+            }
+            wl_compositor::Request::CreateRegion { id } => {
+                data_init.init(id, ());
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<wl_region::WlRegion, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_region::WlRegion,
+        _request: wl_region::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_surface::WlSurface, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &wl_surface::WlSurface,
+        request: wl_surface::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_surface::Request::Attach { buffer, .. } => state.pending_buffer = Some(buffer),
+            wl_surface::Request::Frame { callback } => {
+                state.pending_callbacks.push(data_init.init(callback, ()));
+            }
+            wl_surface::Request::DamageBuffer {
+                x,
+                y,
+                width,
+                height,
+            } => state.pending_damage.push((x, y, width, height)),
+            wl_surface::Request::SetInputRegion { region } => {
+                state.pending_input_region = Some(region.is_some())
+            }
+            wl_surface::Request::Commit => {
+                if let Some(empty) = state.pending_input_region.take() {
+                    state.input_region_empty = empty;
+                }
+                if let Some(buffer) = state.pending_buffer.take() {
+                    if state.mapped != buffer.is_some() {
+                        state.set_keyboard_focus(buffer.is_some());
+                    }
+                    state.mapped = buffer.is_some();
+                    // Like a real compositor, only once there is something to
+                    // show
+                    if state.mapped && state.entered_output.is_none() {
+                        state.move_to_output(0);
+                    }
+                    if let Some(buffer) = buffer {
+                        let data = buffer.data::<BufferData>().unwrap();
+                        let pool = data.pool.lock().unwrap();
+                        let start = data.offset as usize;
+                        let end = start + (data.stride * data.height) as usize;
+                        state.frames.push(Frame {
+                            width: data.width,
+                            height: data.height,
+                            stride: data.stride,
+                            data: pool.map[start..end].to_vec(),
+                            damage: std::mem::take(&mut state.pending_damage),
+                        });
+                    }
+                }
+                let callbacks = std::mem::take(&mut state.pending_callbacks);
+                state.frame_callbacks.extend(callbacks);
+                if !state.hold_frames {
+                    state.frame_done();
+                }
+                if !state.configured {
+                    state.input_region_empty_before_configure = state.input_region_empty;
+                    if let Some(layer_surface) = state.layer_surface.clone() {
+                        state.configured = true;
+                        let serial = state.next_serial();
+                        layer_surface.configure(serial, WIDTH as u32, HEIGHT as u32);
+                    } else if let Some((xdg_surface, toplevel)) = state.toplevel.clone() {
+                        state.configured = true;
+                        let fullscreen = xdg_toplevel::State::Fullscreen as u32;
+                        toplevel.configure(WIDTH, HEIGHT, fullscreen.to_ne_bytes().to_vec());
+                        let serial = state.next_serial();
+                        xdg_surface.configure(serial);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_callback::WlCallback,
+        _request: wl_callback::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<wl_shm::WlShm, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<wl_shm::WlShm>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let shm = data_init.init(resource, ());
+        shm.format(wl_shm::Format::Argb8888);
+        shm.format(wl_shm::Format::Xrgb8888);
+    }
+}
+
+impl Dispatch<wl_shm::WlShm, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_shm::WlShm,
+        request: wl_shm::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_shm::Request::CreatePool { id, fd, size } = request {
+            data_init.init(id, Arc::new(Mutex::new(Pool::new(fd, size))));
+        }
+    }
+}
+
+impl Dispatch<wl_shm_pool::WlShmPool, Arc<Mutex<Pool>>> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_shm_pool::WlShmPool,
+        request: wl_shm_pool::Request,
+        pool: &Arc<Mutex<Pool>>,
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_shm_pool::Request::CreateBuffer {
+                id,
+                offset,
+                width,
+                height,
+                stride,
+                ..
+            } => {
+                data_init.init(
+                    id,
+                    BufferData {
+                        pool: pool.clone(),
+                        offset,
+                        width,
+                        height,
+                        stride,
+                    },
+                );
+            }
+            wl_shm_pool::Request::Resize { size } => {
+                let mut pool = pool.lock().unwrap();
+                pool.map = unsafe {
+                    memmap::MmapOptions::new()
+                        .len(size as usize)
+                        .map_mut(&pool.file)
+                        .unwrap()
+                };
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<wl_buffer::WlBuffer, BufferData> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_buffer::WlBuffer,
+        _request: wl_buffer::Request,
+        _data: &BufferData,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<wl_seat::WlSeat, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<wl_seat::WlSeat>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let seat = data_init.init(resource, ());
+        seat.capabilities(wl_seat::Capability::Pointer | wl_seat::Capability::Keyboard);
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &wl_seat::WlSeat,
+        request: wl_seat::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_seat::Request::GetKeyboard { id } => {
+                let keyboard = data_init.init(id, ());
+                let mut keymap = tempfile::tempfile().unwrap();
+                keymap.write_all(KEYMAP.as_bytes()).unwrap();
+                keymap.write_all(&[0]).unwrap();
+                keyboard.keymap(
+                    wl_keyboard::KeymapFormat::XkbV1,
+                    keymap.as_fd(),
+                    KEYMAP.len() as u32 + 1,
+                );
+                if state.mapped {
+                    let serial = state.next_serial();
+                    keyboard.enter(serial, state.surface.as_ref().unwrap(), Vec::new());
+                }
+                state.keyboards.push(keyboard);
+            }
+            wl_seat::Request::GetPointer { id } => {
+                state.pointers.push(data_init.init(id, ()));
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<wl_keyboard::WlKeyboard, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_keyboard::WlKeyboard,
+        _request: wl_keyboard::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_pointer::WlPointer, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_pointer::WlPointer,
+        _request: wl_pointer::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<zwlr_layer_shell_v1::ZwlrLayerShellV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<zwlr_layer_shell_v1::ZwlrLayerShellV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<zwlr_layer_shell_v1::ZwlrLayerShellV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_layer_shell_v1::ZwlrLayerShellV1,
+        request: zwlr_layer_shell_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_layer_shell_v1::Request::GetLayerSurface { id, .. } = request {
+            state.layer_surface = Some(data_init.init(id, ()));
+        }
+    }
+}
+
+impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+        _request: zwlr_layer_surface_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<xdg_wm_base::XdgWmBase, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<xdg_wm_base::XdgWmBase>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<xdg_wm_base::XdgWmBase, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &xdg_wm_base::XdgWmBase,
+        request: xdg_wm_base::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let xdg_wm_base::Request::GetXdgSurface { id, .. } = request {
+            data_init.init(id, ());
+        }
+    }
+}
+
+impl Dispatch<xdg_surface::XdgSurface, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &xdg_surface::XdgSurface,
+        request: xdg_surface::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let xdg_surface::Request::GetToplevel { id } = request {
+            state.toplevel = Some((resource.clone(), data_init.init(id, ())));
+        }
+    }
+}
+
+impl Dispatch<xdg_toplevel::XdgToplevel, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &xdg_toplevel::XdgToplevel,
+        request: xdg_toplevel::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_toplevel::Request::SetFullscreen { .. } => state.fullscreen = true,
+            xdg_toplevel::Request::UnsetFullscreen => state.fullscreen = false,
+            xdg_toplevel::Request::SetAppId { app_id } => state.app_id = Some(app_id),
+            _ => (),
+        }
+    }
+}
+
+impl GlobalDispatch<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1,
+        request: zwlr_virtual_pointer_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointer { id, .. }
+            | zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointerWithOutput {
+                id, ..
+            } => {
+                data_init.init(id, ());
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1,
+        request: zwlr_virtual_pointer_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_virtual_pointer_v1::Request::MotionAbsolute {
+                time,
+                x,
+                y,
+                x_extent,
+                y_extent,
+            } => {
+                state.pointer_times.push(time);
+                state.pointer_requests.push(PointerRequest::MotionAbsolute {
+                    x,
+                    y,
+                    x_extent,
+                    y_extent,
+                })
+            }
+            zwlr_virtual_pointer_v1::Request::Button {
+                time,
+                button,
+                state: WEnum::Value(button_state),
+            } => {
+                state.pointer_times.push(time);
+                state.pointer_requests.push(PointerRequest::Button {
+                    button,
+                    state: button_state,
+                })
+            }
+            zwlr_virtual_pointer_v1::Request::Frame => {
+                state.pointer_requests.push(PointerRequest::Frame)
+            }
+            _ => (),
+        }
+    }
+}
+
+impl GlobalDispatch<org_kde_kwin_fake_input::OrgKdeKwinFakeInput, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<org_kde_kwin_fake_input::OrgKdeKwinFakeInput>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<org_kde_kwin_fake_input::OrgKdeKwinFakeInput, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &org_kde_kwin_fake_input::OrgKdeKwinFakeInput,
+        request: org_kde_kwin_fake_input::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            org_kde_kwin_fake_input::Request::Authenticate { .. } => {
+                state.pointer_requests.push(PointerRequest::Authenticate)
+            }
+            org_kde_kwin_fake_input::Request::PointerMotionAbsolute { x, y } => state
+                .pointer_requests
+                .push(PointerRequest::FakeMotionAbsolute { x, y }),
+            org_kde_kwin_fake_input::Request::Button {
+                button,
+                state: button_state,
+            } => state.pointer_requests.push(PointerRequest::FakeButton {
+                button,
+                state: button_state,
+            }),
+            _ => (),
+        }
+    }
+}
+
+impl GlobalDispatch<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1,
+        request: zwp_virtual_keyboard_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let zwp_virtual_keyboard_manager_v1::Request::CreateVirtualKeyboard { id, .. } = request;
+        data_init.init(id, ());
+    }
+}
+
+impl Dispatch<zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+        request: zwp_virtual_keyboard_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_virtual_keyboard_v1::Request::Keymap { fd, size, .. } => {
+                let mut file = File::from(fd);
+                let mut keymap = vec![0; size as usize];
+                file.seek(SeekFrom::Start(0)).unwrap();
+                file.read_exact(&mut keymap).unwrap();
+                state.keyboard_requests.push(KeyboardRequest::Keymap {
+                    keymap: String::from_utf8(keymap)
+                        .unwrap()
+                        .trim_end_matches('\0')
+                        .into(),
+                    overlay_mapped: state.mapped,
+                })
+            }
+            zwp_virtual_keyboard_v1::Request::Key {
+                key,
+                state: key_state,
+                ..
+            } => state.keyboard_requests.push(KeyboardRequest::Key {
+                key,
+                state: key_state,
+            }),
+            zwp_virtual_keyboard_v1::Request::Modifiers { mods_depressed, .. } => {
+                state.keyboard_requests.push(KeyboardRequest::Modifiers {
+                    depressed: mods_depressed,
+                })
+            }
+            _ => (),
+        }
+    }
+}
+
+impl GlobalDispatch<wl_output::WlOutput, usize> for State {
+    fn bind(
+        state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<wl_output::WlOutput>,
+        index: &usize,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let (name, description, x, y, physical_width, physical_height) = OUTPUTS[*index];
+        let output = data_init.init(resource, ());
+        state.outputs.push((*index, output.clone()));
+        output.geometry(
+            x,
+            y,
+            physical_width,
+            physical_height,
+            wl_output::Subpixel::Unknown,
+            "mock".into(),
+            "mock".into(),
+            wl_output::Transform::Normal,
+        );
+        output.mode(wl_output::Mode::Current, WIDTH, HEIGHT, 60000);
+        if output.version() >= 4 {
+            output.name(name.into());
+            output.description(description.into());
+        }
+        if output.version() >= 2 {
+            output.done();
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_output::WlOutput,
+        _request: wl_output::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+impl GlobalDispatch<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_screencopy_manager_v1::Request::CaptureOutput { frame, .. } = request {
+            let frame = data_init.init(frame, ());
+            frame.buffer(
+                wl_shm::Format::Xrgb8888,
+                WIDTH as u32,
+                HEIGHT as u32,
+                WIDTH as u32 * 4,
+            );
+            if frame.version() >= 3 {
+                frame.buffer_done();
+            }
+        }
+    }
+}
+
+impl Dispatch<zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        resource: &zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_screencopy_frame_v1::Request::Copy { buffer } = request {
+            let data = buffer.data::<BufferData>().unwrap();
+            let mut pool = data.pool.lock().unwrap();
+            for y in 0..data.height {
+                for x in 0..data.width {
+                    let offset = (data.offset + y * data.stride + x * 4) as usize;
+                    pool.map[offset..offset + 4].copy_from_slice(&screen_pixel(x, y).to_le_bytes());
+                }
+            }
+            state.captures.push(state.frames.len());
+            resource.flags(zwlr_screencopy_frame_v1::Flags::empty());
+            resource.ready(0, 0, 0);
+        }
+    }
+}
+
+impl GlobalDispatch<wl_data_device_manager::WlDataDeviceManager, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<wl_data_device_manager::WlDataDeviceManager>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<wl_data_device_manager::WlDataDeviceManager, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_data_device_manager::WlDataDeviceManager,
+        request: wl_data_device_manager::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_data_device_manager::Request::CreateDataSource { id } => {
+                data_init.init(id, Mutex::new(Vec::<String>::new()));
+            }
+            wl_data_device_manager::Request::GetDataDevice { id, .. } => {
+                data_init.init(id, ());
+            }
+            _ => (),
+        }
+    }
+}
+
+// The user data is what the source offers
+impl Dispatch<wl_data_source::WlDataSource, Mutex<Vec<String>>> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &wl_data_source::WlDataSource,
+        request: wl_data_source::Request,
+        mime_types: &Mutex<Vec<String>>,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_data_source::Request::Offer { mime_type } = request {
+            mime_types.lock().unwrap().push(mime_type);
+        }
+    }
+}
+
+impl Dispatch<wl_data_device::WlDataDevice, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &wl_data_device::WlDataDevice,
+        request: wl_data_device::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let wl_data_device::Request::SetSelection { source, .. } = request {
+            state.set_selection(source.map(Selection::DataDevice));
+        }
+    }
+}
+
+impl GlobalDispatch<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1, ()> for State {
+    fn bind(
+        _state: &mut Self,
+        _handle: &DisplayHandle,
+        _client: &Client,
+        resource: New<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+        request: zwlr_data_control_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            zwlr_data_control_manager_v1::Request::CreateDataSource { id } => {
+                data_init.init(id, Mutex::new(Vec::<String>::new()));
+            }
+            zwlr_data_control_manager_v1::Request::GetDataDevice { id, .. } => {
+                data_init.init(id, ());
+            }
+            _ => (),
+        }
+    }
+}
+
+// The user data is what the source offers
+impl Dispatch<zwlr_data_control_source_v1::ZwlrDataControlSourceV1, Mutex<Vec<String>>> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
+        request: zwlr_data_control_source_v1::Request,
+        mime_types: &Mutex<Vec<String>>,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_data_control_source_v1::Request::Offer { mime_type } = request {
+            mime_types.lock().unwrap().push(mime_type);
+        }
+    }
+}
+
+impl Dispatch<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
+        request: zwlr_data_control_device_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_data_control_device_v1::Request::SetSelection { source } = request {
+            state.set_selection(source.map(Selection::DataControl));
+        }
+    }
+}
+
+impl GlobalDispatch<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, ()> for State {
+    fn bind(
+        state: &mut Self,
+        handle: &DisplayHandle,
+        client: &Client,
+        resource: New<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let manager = data_init.init(resource, ());
+        // The app creates its window before binding the manager, so it is
+        // known by now
+        let own_window = state.app_id.as_deref().map(|app_id| ("keynav", app_id));
+        for (index, (title, app_id)) in WINDOWS.into_iter().chain(own_window).enumerate() {
+            let toplevel = client
+                .create_resource::<zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1, usize, State>(
+                    handle,
+                    manager.version(),
+                    index,
+                )
+                .unwrap();
+            manager.toplevel(&toplevel);
+            toplevel.title(title.into());
+            toplevel.app_id(app_id.into());
+            if index == FOCUSED_WINDOW {
+                let activated = zwlr_foreign_toplevel_handle_v1::State::Activated as u32;
+                toplevel.state(activated.to_ne_bytes().to_vec());
+            }
+            toplevel.done();
+            state.windows.push(toplevel);
+        }
+    }
+}
+
+impl Dispatch<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, ()> for State {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1,
+        _request: zwlr_foreign_toplevel_manager_v1::Request,
+        _data: &(),
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+    }
+}
+
+// The user data is the index into [WINDOWS]
+impl Dispatch<zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1, usize> for State {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _resource: &zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1,
+        request: zwlr_foreign_toplevel_handle_v1::Request,
+        index: &usize,
+        _dhandle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        if let zwlr_foreign_toplevel_handle_v1::Request::Activate { .. } = request {
+            state.activated.push(*index);
+        }
+    }
+}
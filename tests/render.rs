@@ -0,0 +1,300 @@
+use std::env;
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use keynav_wayland::config::Easing;
+use keynav_wayland::render::RenderManager;
+
+// Golden images live in tests/golden. Run with UPDATE_GOLDEN=1 to (re)generate
+// them after an intentional change to the rendering, and check the new images
+// in alongside the change.
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", name))
+}
+
+fn pixels(surface: &mut cairo::ImageSurface) -> (i32, i32, Vec<u8>) {
+    let (width, height) = (surface.width(), surface.height());
+    (width, height, surface.data().unwrap().to_vec())
+}
+
+fn render(width: u32, height: u32, region: (f64, f64, f64, f64)) -> RenderManager {
+    let mut renderer = RenderManager::init(cairo::Format::ARgb32, width, height).unwrap();
+    renderer.update_active_region(cairo::Rectangle {
+        x: region.0,
+        y: region.1,
+        width: region.2,
+        height: region.3,
+    });
+    renderer.redraw().unwrap();
+    renderer
+}
+
+fn assert_matches_golden(name: &str, renderer: &RenderManager) {
+    let mut png = Vec::new();
+    renderer.write_png(&mut png).unwrap();
+
+    let golden = golden_path(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&golden, &png).unwrap();
+        return;
+    }
+
+    let mut expected = cairo::ImageSurface::create_from_png(
+        &mut File::open(&golden)
+            .unwrap_or_else(|_| panic!("Missing {:?}, run with UPDATE_GOLDEN=1", golden)),
+    )
+    .unwrap();
+    let mut actual = cairo::ImageSurface::create_from_png(&mut png.as_slice()).unwrap();
+    if pixels(&mut expected) != pixels(&mut actual) {
+        let output = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.png", name));
+        std::fs::write(&output, &png).unwrap();
+        panic!(
+            "Rendering of {} differs from {:?}, actual output written to {:?}",
+            name, golden, output
+        );
+    }
+}
+
+#[test]
+fn full_region() {
+    assert_matches_golden("full_region", &render(160, 90, (0.0, 0.0, 1.0, 1.0)));
+}
+
+#[test]
+fn left_half() {
+    assert_matches_golden("left_half", &render(160, 90, (0.0, 0.0, 0.5, 1.0)));
+}
+
+#[test]
+fn small_region() {
+    assert_matches_golden("small_region", &render(160, 90, (0.25, 0.5, 0.125, 0.25)));
+}
+
+#[test]
+fn bottom_right_corner() {
+    assert_matches_golden(
+        "bottom_right_corner",
+        &render(160, 90, (0.75, 0.75, 0.25, 0.25)),
+    );
+}
+
+// Like [render], for an output with a scale of 2
+fn render_scaled(width: u32, height: u32, region: (f64, f64, f64, f64)) -> RenderManager {
+    let mut renderer = render(width, height, region);
+    renderer.set_scale(2).unwrap();
+    renderer
+}
+
+#[test]
+fn hidpi_small_region() {
+    let renderer = render_scaled(160, 90, (0.25, 0.5, 0.125, 0.25));
+    assert_eq!((320, 180), (renderer.get_width(), renderer.get_height()));
+    assert_eq!((160, 90), renderer.get_surface_size());
+    assert_matches_golden("hidpi_small_region", &renderer);
+
+    // The left border stays within the pixels on either side of x = 80,
+    // as it would at scale 1, instead of also covering the ones next to them
+    let mut png = Vec::new();
+    renderer.write_png(&mut png).unwrap();
+    let (width, _, data) =
+        pixels(&mut cairo::ImageSurface::create_from_png(&mut png.as_slice()).unwrap());
+    let row = &data[(100 * width * 4) as usize..(101 * width * 4) as usize];
+    let pixel = |x: usize| &row[x * 4..x * 4 + 4];
+    assert_eq!([0; 4], pixel(78));
+    assert_ne!([0; 4], pixel(79));
+    assert_ne!(pixel(81), pixel(80));
+    assert_eq!([0x33; 4], pixel(81));
+}
+
+#[test]
+fn hidpi_magnifier() {
+    // Twice as big as at scale 1, as far as the surface allows
+    let mut renderer = render_scaled(200, 150, (0.25, 0.5, 0.03, 0.05));
+    renderer.set_magnifier(Some(screen(400, 300)));
+    renderer.redraw().unwrap();
+    assert_matches_golden("hidpi_magnifier", &renderer);
+}
+
+#[test]
+fn rebound_surface_matches_fresh_one() {
+    let mut renderer = render(320, 180, (0.25, 0.5, 0.125, 0.25));
+    renderer.set_bounds(160, 90).unwrap();
+    assert_matches_golden("small_region", &renderer);
+}
+
+#[test]
+fn partial_redraws_match_full_redraw() {
+    let regions = [
+        (0.0, 0.0, 0.5, 1.0),
+        (0.0, 0.5, 0.5, 0.5),
+        (0.5, 0.5, 0.5, 0.5),
+        (0.6, 0.55, 0.1, 0.1),
+        (0.05, 0.3, 0.1, 0.1),
+    ];
+    let mut renderer = render(160, 90, (0.0, 0.0, 1.0, 1.0));
+    for region in regions {
+        renderer.update_active_region(cairo::Rectangle {
+            x: region.0,
+            y: region.1,
+            width: region.2,
+            height: region.3,
+        });
+        renderer.redraw().unwrap();
+
+        let mut partial = Vec::new();
+        renderer.write_png(&mut partial).unwrap();
+        let mut full = Vec::new();
+        render(160, 90, region).write_png(&mut full).unwrap();
+        assert!(
+            pixels(&mut cairo::ImageSurface::create_from_png(&mut partial.as_slice()).unwrap())
+                == pixels(&mut cairo::ImageSurface::create_from_png(&mut full.as_slice()).unwrap()),
+            "Partial redraw to {:?} differs from a full redraw",
+            region
+        );
+    }
+}
+
+#[test]
+fn damage_covers_old_and_new_region() {
+    let mut renderer = render(160, 90, (0.0, 0.0, 0.5, 1.0));
+    renderer.take_damage();
+    renderer.update_active_region(cairo::Rectangle {
+        x: 0.25,
+        y: 0.5,
+        width: 0.25,
+        height: 0.5,
+    });
+    renderer.redraw().unwrap();
+    assert_eq!(vec![(0, 0, 81, 90)], renderer.take_damage());
+
+    renderer.update_active_region(cairo::Rectangle {
+        x: 0.5,
+        y: 0.5,
+        width: 0.25,
+        height: 0.5,
+    });
+    renderer.redraw().unwrap();
+    assert_eq!(
+        vec![(39, 44, 42, 46), (79, 44, 42, 46)],
+        renderer.take_damage()
+    );
+
+    renderer.redraw().unwrap();
+    assert_eq!(Vec::<(i32, i32, i32, i32)>::new(), renderer.take_damage());
+
+    // Moving across the diagonal only damages the corners it moves between
+    renderer.update_active_region(cairo::Rectangle {
+        x: 0.0,
+        y: 0.0,
+        width: 0.25,
+        height: 0.25,
+    });
+    renderer.redraw().unwrap();
+    renderer.take_damage();
+    renderer.update_active_region(cairo::Rectangle {
+        x: 0.25,
+        y: 0.25,
+        width: 0.25,
+        height: 0.25,
+    });
+    renderer.redraw().unwrap();
+    assert_eq!(
+        vec![(0, 0, 41, 24), (39, 21, 42, 25)],
+        renderer.take_damage()
+    );
+}
+
+#[test]
+fn animation_interpolates_between_regions() {
+    let mut renderer = render(160, 90, (0.0, 0.0, 1.0, 1.0));
+    renderer.set_animation(Duration::from_millis(100), Easing::Linear);
+    let left_half = cairo::Rectangle {
+        x: 0.0,
+        y: 0.0,
+        width: 0.5,
+        height: 1.0,
+    };
+    renderer.update_active_region(left_half);
+    assert!(renderer.is_animating());
+
+    // Time starts with the first tick, so this still shows the old region
+    renderer.tick(1000);
+    assert_eq!(1.0, renderer.get_displayed_region().width);
+    renderer.tick(1050);
+    assert_eq!(0.75, renderer.get_displayed_region().width);
+    renderer.tick(1100);
+    assert_eq!(left_half, renderer.get_displayed_region());
+    assert!(!renderer.is_animating());
+}
+
+#[test]
+fn no_animation_when_disabled() {
+    let mut renderer = render(160, 90, (0.0, 0.0, 1.0, 1.0));
+    renderer.set_animation(Duration::ZERO, Easing::Linear);
+    renderer.update_active_region(cairo::Rectangle {
+        x: 0.0,
+        y: 0.0,
+        width: 0.5,
+        height: 1.0,
+    });
+    assert!(!renderer.is_animating());
+    assert_eq!(0.5, renderer.get_displayed_region().width);
+}
+
+// Checkerboard of 3x3 pixel cells in a few colours
+fn screen(width: i32, height: i32) -> cairo::ImageSurface {
+    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
+    let context = cairo::Context::new(&surface).unwrap();
+    let colours = [
+        (0.9, 0.2, 0.2),
+        (0.2, 0.8, 0.3),
+        (0.2, 0.3, 0.9),
+        (0.9, 0.9, 0.9),
+    ];
+    for y in 0..height / 3 + 1 {
+        for x in 0..width / 3 + 1 {
+            let (r, g, b) = colours[((x + 2 * y) % 4) as usize];
+            context.set_source_rgb(r, g, b);
+            context.rectangle(x as f64 * 3.0, y as f64 * 3.0, 3.0, 3.0);
+            context.fill().unwrap();
+        }
+    }
+    drop(context);
+    surface
+}
+
+#[test]
+fn magnifier() {
+    let mut renderer = render(320, 180, (0.25, 0.5, 0.03, 0.05));
+    renderer.set_magnifier(Some(screen(320, 180)));
+    renderer.redraw().unwrap();
+    assert_matches_golden("magnifier", &renderer);
+}
+
+#[test]
+fn magnifier_flips_at_right_edge() {
+    let mut renderer = render(320, 180, (0.9, 0.1, 0.05, 0.05));
+    renderer.set_magnifier(Some(screen(320, 180)));
+    renderer.redraw().unwrap();
+    assert_matches_golden("magnifier_right_edge", &renderer);
+
+    // Hiding it again only repaints around the region and the magnifier
+    renderer.take_damage();
+    renderer.set_magnifier(None);
+    renderer.redraw().unwrap();
+    let mut partial = Vec::new();
+    renderer.write_png(&mut partial).unwrap();
+    let mut full = Vec::new();
+    render(320, 180, (0.9, 0.1, 0.05, 0.05))
+        .write_png(&mut full)
+        .unwrap();
+    assert!(
+        pixels(&mut cairo::ImageSurface::create_from_png(&mut partial.as_slice()).unwrap())
+            == pixels(&mut cairo::ImageSurface::create_from_png(&mut full.as_slice()).unwrap())
+    );
+}